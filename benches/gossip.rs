@@ -0,0 +1,349 @@
+use {
+    cluster_mocks::{
+        crds_value::{CrdsValueModel, CrdsValueType},
+        gossip::{make_gossip_nodes, timestamp, Config, CrdsKey, Discovery, Node, Packet},
+        push_active_set::{
+            PushActiveSet, NUM_PUSH_ACTIVE_SET_ENTRIES, PUSH_ACTIVE_SET_FANOUT_MULTIPLE,
+        },
+        received_cache::{ReceivedCache, ReceivedCacheScorer},
+        simulation::{ClusterSource, Simulation},
+        stakes::{make_synthetic_stakes, read_stakes, StakeDistribution, StakeViewModel},
+        NodeId, Router,
+    },
+    criterion::{
+        black_box, criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion, Throughput,
+    },
+    crossbeam_channel::Sender,
+    rand::{seq::SliceRandom, Rng, SeedableRng},
+    rand_chacha::ChaChaRng,
+    solana_sdk::pubkey::Pubkey,
+    std::{
+        cmp::Reverse,
+        sync::Arc,
+        time::{Duration, Instant},
+    },
+};
+
+// Number of staked nodes in the synthetic mainnet-like cluster.
+const MAINNET_CLUSTER_SIZE: usize = 3132;
+const SYNTHETIC_CLUSTER_SIZE: usize = 10_000;
+// Number of packets handled in each iteration of the packet benchmarks.
+const NUM_PACKETS: usize = 4096;
+// Number of received-cache entries pruned in each iteration.
+const NUM_PRUNE_ORIGINS: usize = 1024;
+
+struct Cluster {
+    name: &'static str,
+    stakes: Vec<(Pubkey, /*stake:*/ u64)>,
+}
+
+// Mainnet-like cluster, loaded from a stakes snapshot if STAKES_FILE is set,
+// and a synthetic cluster of 10k nodes, both with heavy-tailed stakes.
+fn make_clusters() -> Vec<Cluster> {
+    let mut rng = ChaChaRng::seed_from_u64(42);
+    let mainnet = match std::env::var("STAKES_FILE") {
+        Ok(path) => read_stakes(path).unwrap(),
+        Err(_) => make_synthetic_stakes(
+            &mut rng,
+            MAINNET_CLUSTER_SIZE,
+            StakeDistribution::Pareto(1.2),
+        ),
+    };
+    let synthetic = make_synthetic_stakes(
+        &mut rng,
+        SYNTHETIC_CLUSTER_SIZE,
+        StakeDistribution::Pareto(1.2),
+    );
+    vec![
+        Cluster {
+            name: "mainnet",
+            stakes: mainnet,
+        },
+        Cluster {
+            name: "synthetic-10k",
+            stakes: synthetic,
+        },
+    ]
+}
+
+fn make_config(num_nodes: usize) -> Config {
+    Config {
+        gossip_push_fanout: 6.0,
+        gossip_push_wide_fanout: 6.0,
+        rotate_active_set_rounds: 35,
+        gossip_prune_min_ingress_nodes: 3,
+        gossip_prune_max_origins: 32,
+        gossip_prune_timeout: Duration::from_millis(500),
+        gossip_prune_stake_threshold_pct: 0.15,
+        received_cache_capacity: 2 * num_nodes,
+        received_cache_min_num_upserts: 20,
+        received_cache_entry_capacity: 50,
+        received_cache_num_dups_threshold: 2,
+        received_cache_scorer: ReceivedCacheScorer::NumDups,
+        gossip_push_capacity: 5,
+        packet_drop_rate: 0.0,
+        crds_values: CrdsValueModel::mainnet(),
+        discovery: Discovery::Global,
+        stake_view: StakeViewModel::EXACT,
+        crds_purge_rounds: 10,
+        crds_timeout_staked: Duration::from_secs(172_800),
+        crds_timeout_unstaked: Duration::from_secs(15),
+        num_threads: 1,
+        run_duration: Duration::ZERO,
+        warm_up_rounds: 0,
+        trace: None,
+    }
+}
+
+impl Cluster {
+    // Indexed by NodeId.
+    fn stakes(&self) -> Vec<u64> {
+        self.stakes.iter().map(|&(_pubkey, stake)| stake).collect()
+    }
+
+    // Returns the first node of the cluster along with the sender to its
+    // channel. All nodes are built so that the node's view of stakes covers
+    // the whole cluster.
+    fn make_node(&self, config: &Config) -> (Node, Sender<Arc<Packet>>) {
+        let mut rng = ChaChaRng::seed_from_u64(42);
+        let stakes = self.stakes.iter().copied();
+        make_gossip_nodes(&mut rng, stakes, config).swap_remove(0)
+    }
+}
+
+// Push packets from random peers for random votes of the cluster, which
+// dominate gossip traffic.
+fn make_push_packets<R: Rng>(rng: &mut R, num_nodes: usize, config: &Config) -> Vec<Packet> {
+    let num_votes = config.crds_values.get(CrdsValueType::Vote).count;
+    let wallclock = timestamp();
+    (0..NUM_PACKETS)
+        .map(|_| Packet::Push {
+            from: NodeId::from(rng.gen_range(1, num_nodes)),
+            key: CrdsKey::new(
+                NodeId::from(rng.gen_range(1, num_nodes)),
+                CrdsValueType::Vote,
+                rng.gen_range(0, num_votes),
+            ),
+            ordinal: 1,
+            wallclock,
+            timestamp: 0,
+        })
+        .collect()
+}
+
+fn with_ordinal(packet: &Packet, ordinal: u64) -> Arc<Packet> {
+    let Packet::Push {
+        from,
+        key,
+        wallclock,
+        timestamp,
+        ..
+    } = *packet else {
+        unreachable!();
+    };
+    Arc::new(Packet::Push {
+        from,
+        key,
+        ordinal,
+        wallclock,
+        timestamp,
+    })
+}
+
+// Cost of gossip rounds in a warm cluster, reported as node rounds per
+// second. Each node holds 25 push active-set entries of 3 * fanout bloom
+// filters, 4KB each, so a full mainnet-size cluster needs more than 5GB;
+// rounds are instead simulated among the highest staked nodes of the
+// mainnet-like cluster.
+fn bench_gossip_rounds(c: &mut Criterion) {
+    const NUM_NODES: usize = 1000;
+    const NUM_WARM_UP_STEPS: usize = 5;
+    let mut group = c.benchmark_group("gossip_rounds");
+    group.sample_size(10);
+    for mut cluster in make_clusters().into_iter().take(1) {
+        cluster
+            .stakes
+            .sort_unstable_by_key(|&(_pubkey, stake)| Reverse(stake));
+        cluster.stakes.truncate(NUM_NODES);
+        let config = make_config(cluster.stakes.len());
+        let mut simulation = Simulation::builder()
+            .cluster(ClusterSource::Stakes(cluster.stakes))
+            .config(config)
+            .seed(42)
+            .build()
+            .unwrap();
+        for _ in 0..NUM_WARM_UP_STEPS {
+            simulation.step().unwrap();
+        }
+        group.throughput(Throughput::Elements(simulation.pubkeys().len() as u64));
+        let id = format!("{}-top-{NUM_NODES}", cluster.name);
+        group.bench_function(id, |b| b.iter(|| simulation.step().unwrap()));
+    }
+    group.finish();
+}
+
+// Node::consume_packets, by outcome of upserting the pushed values.
+fn bench_consume_packets(c: &mut Criterion) {
+    let mut rng = ChaChaRng::seed_from_u64(42);
+    let mut group = c.benchmark_group("consume_packets");
+    group.throughput(Throughput::Elements(NUM_PACKETS as u64));
+    for cluster in make_clusters() {
+        let config = make_config(cluster.stakes.len());
+        let stakes = cluster.stakes();
+        let (mut node, sender) = cluster.make_node(&config);
+        let packets = make_push_packets(&mut rng, stakes.len(), &config);
+        let mut ordinal = 1;
+        group.bench_function(BenchmarkId::new("upsert", cluster.name), |b| {
+            b.iter_batched(
+                || {
+                    ordinal += 1;
+                    for packet in &packets {
+                        sender.send(with_ordinal(packet, ordinal)).unwrap();
+                    }
+                },
+                |()| node.consume_packets(&config, &()),
+                BatchSize::PerIteration,
+            )
+        });
+        for (name, offset) in [("duplicate", 0), ("outdated", 1)] {
+            group.bench_function(BenchmarkId::new(name, cluster.name), |b| {
+                b.iter_batched(
+                    || {
+                        for packet in &packets {
+                            sender.send(with_ordinal(packet, ordinal - offset)).unwrap();
+                        }
+                    },
+                    |()| node.consume_packets(&config, &()),
+                    BatchSize::PerIteration,
+                )
+            });
+        }
+    }
+    group.finish();
+}
+
+// PushActiveSet::rotate, reported as active-set entries (i.e. stake
+// buckets) rotated per second.
+fn bench_rotate(c: &mut Criterion) {
+    let mut rng = ChaChaRng::seed_from_u64(42);
+    let mut group = c.benchmark_group("rotate");
+    group.throughput(Throughput::Elements(NUM_PUSH_ACTIVE_SET_ENTRIES as u64));
+    for cluster in make_clusters() {
+        let config = make_config(cluster.stakes.len());
+        let stakes = cluster.stakes();
+        let nodes: Vec<NodeId> = (1..stakes.len()).map(NodeId::from).collect();
+        let size = config.gossip_push_fanout as usize * PUSH_ACTIVE_SET_FANOUT_MULTIPLE;
+        let mut active_set = PushActiveSet::default();
+        active_set.rotate(&mut rng, size, nodes.len(), &nodes, &stakes);
+        group.bench_function(cluster.name, |b| {
+            b.iter(|| active_set.rotate(&mut rng, size, nodes.len(), &nodes, &stakes))
+        });
+    }
+    group.finish();
+}
+
+// ReceivedCache::prune on full entries, reported as origins pruned per
+// second.
+fn bench_received_cache_prune(c: &mut Criterion) {
+    let mut rng = ChaChaRng::seed_from_u64(42);
+    let mut group = c.benchmark_group("received_cache_prune");
+    group.throughput(Throughput::Elements(NUM_PRUNE_ORIGINS as u64));
+    for cluster in make_clusters() {
+        let config = make_config(cluster.stakes.len());
+        let stakes = cluster.stakes();
+        let node = NodeId(0);
+        let nodes: Vec<NodeId> = (1..stakes.len()).map(NodeId::from).collect();
+        let origins: Vec<NodeId> = nodes
+            .choose_multiple(&mut rng, NUM_PRUNE_ORIGINS)
+            .copied()
+            .collect();
+        let mut cache = ReceivedCache::new(
+            NUM_PRUNE_ORIGINS,
+            config.received_cache_min_num_upserts,
+            config.received_cache_entry_capacity,
+            config.received_cache_num_dups_threshold,
+            config.received_cache_scorer,
+        );
+        for &origin in &origins {
+            for _ in 0..config.received_cache_min_num_upserts {
+                let peers = nodes.choose_multiple(&mut rng, config.received_cache_entry_capacity);
+                for (num_dups, &peer) in peers.enumerate() {
+                    cache.record(origin, peer, num_dups, Duration::ZERO);
+                }
+            }
+        }
+        group.bench_function(cluster.name, |b| {
+            b.iter_batched(
+                || cache.clone(),
+                |mut cache| {
+                    for &origin in &origins {
+                        black_box(cache.prune(
+                            node,
+                            origin,
+                            config.gossip_prune_stake_threshold_pct,
+                            config.gossip_prune_min_ingress_nodes,
+                            &stakes,
+                        ));
+                    }
+                },
+                BatchSize::LargeInput,
+            )
+        });
+    }
+    group.finish();
+}
+
+// Router::send to random nodes, with and without packet drops.
+fn bench_router_send(c: &mut Criterion) {
+    let mut rng = ChaChaRng::seed_from_u64(42);
+    let packet = Arc::new(Packet::Push {
+        from: NodeId(0),
+        key: CrdsKey::new(NodeId(0), CrdsValueType::Vote, 0),
+        ordinal: 1,
+        wallclock: 0,
+        timestamp: 0,
+    });
+    let mut group = c.benchmark_group("router_send");
+    group.throughput(Throughput::Elements(NUM_PACKETS as u64));
+    for cluster in make_clusters() {
+        let num_nodes = cluster.stakes.len();
+        let (senders, receivers): (Vec<_>, Vec<_>) = (0..num_nodes)
+            .map(|_| crossbeam_channel::unbounded())
+            .unzip();
+        let nodes: Vec<NodeId> = (0..NUM_PACKETS)
+            .map(|_| NodeId::from(rng.gen_range(0, num_nodes)))
+            .collect();
+        for packet_drop_rate in [0.0, 0.1] {
+            let router = Router::new(packet_drop_rate, senders.clone()).unwrap();
+            let id = BenchmarkId::new(cluster.name, format!("drop-rate-{packet_drop_rate}"));
+            group.bench_function(id, |b| {
+                b.iter_custom(|iters| {
+                    let now = Instant::now();
+                    for _ in 0..iters {
+                        for &node in &nodes {
+                            router.send(&mut rng, node, packet.clone()).unwrap();
+                        }
+                    }
+                    let elapsed = now.elapsed();
+                    // Drain channels so that memory does not grow across
+                    // samples.
+                    for receiver in &receivers {
+                        receiver.try_iter().for_each(drop);
+                    }
+                    elapsed
+                })
+            });
+        }
+    }
+    group.finish();
+}
+
+criterion_group!(
+    benches,
+    bench_gossip_rounds,
+    bench_consume_packets,
+    bench_rotate,
+    bench_received_cache_prune,
+    bench_router_send,
+);
+criterion_main!(benches);
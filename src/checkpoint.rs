@@ -0,0 +1,233 @@
+use {
+    crate::{
+        gossip::{timestamp, CrdsEntry, CrdsKey, Node, NodeStats, Packet, TraceEdge},
+        received_cache::ReceivedCache,
+        Error, NodeId, Router,
+    },
+    crossbeam_channel::Sender,
+    serde::{Deserialize, Serialize},
+    solana_bloom::bloom::Bloom,
+    solana_sdk::pubkey::Pubkey,
+    std::{
+        borrow::BorrowMut,
+        collections::HashMap,
+        fs::File,
+        io::{BufReader, BufWriter, Write},
+        path::Path,
+        sync::Arc,
+    },
+};
+
+/// Full state of a simulated cluster, which can be written to disk and
+/// loaded back to resume, or fork, a run from a warm cluster.
+#[derive(Deserialize, Serialize)]
+pub struct Checkpoint {
+    // Wallclock (us) when the checkpoint was taken.
+    timestamp: u64,
+    // Indexed by NodeId.
+    nodes: Vec<NodeCheckpoint>,
+}
+
+#[derive(Deserialize, Serialize)]
+pub(crate) struct NodeCheckpoint {
+    pub(crate) num_gossip_rounds: usize,
+    pub(crate) id: NodeId,
+    pub(crate) pubkey: Pubkey,
+    pub(crate) stake: u64,
+    pub(crate) stakes: Vec<u64>,
+    pub(crate) next_stakes: Option<(/*round:*/ usize, Vec<u64>)>,
+    pub(crate) table: HashMap<CrdsKey, CrdsEntry>,
+    pub(crate) entrypoints: Vec<NodeId>,
+    pub(crate) discovery_round: Option<usize>,
+    // Nodes in each push active-set entry and the origins they have pruned.
+    pub(crate) active_set: Vec<Vec<(NodeId, Bloom<NodeId>)>>,
+    pub(crate) received_cache: ReceivedCache,
+    pub(crate) stats: NodeStats,
+    pub(crate) trace: Vec<TraceEdge>,
+    // ChaChaRng state, as its seed and position in the key stream.
+    pub(crate) rng_seed: [u8; 32],
+    pub(crate) rng_word_pos: u128,
+    // Packets in flight to this node, in order of arrival.
+    pub(crate) packets: Vec<Packet>,
+}
+
+impl Checkpoint {
+    /// Captures the state of the nodes, which should be indexed by NodeId.
+    /// Packets in flight are recorded and sent back through the router, so
+    /// that the run can continue after the checkpoint.
+    pub fn new<I, T>(nodes: I, router: &Router<Arc<Packet>>) -> Result<Self, Error>
+    where
+        I: IntoIterator<Item = T>,
+        T: BorrowMut<Node>,
+    {
+        let nodes = nodes
+            .into_iter()
+            .map(|mut node| node.borrow_mut().checkpoint(router))
+            .collect::<Result<_, Error>>()?;
+        Ok(Self {
+            timestamp: timestamp(),
+            nodes,
+        })
+    }
+
+    pub fn num_nodes(&self) -> usize {
+        self.nodes.len()
+    }
+
+    pub fn read_from_file<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        let file = BufReader::new(File::open(path)?);
+        Ok(bincode::deserialize_from(file)?)
+    }
+
+    pub fn write_to_file<P: AsRef<Path>>(&self, path: P) -> Result<(), Error> {
+        let mut file = BufWriter::new(File::create(path)?);
+        bincode::serialize_into(&mut file, self)?;
+        Ok(file.flush()?)
+    }
+
+    /// Returns the checkpointed nodes, each with the sender to its channel
+    /// which already holds the packets in flight. Timestamps are shifted
+    /// forward by the wallclock time elapsed since the checkpoint, so that
+    /// delays and prune timeouts are not affected by the gap.
+    pub fn into_nodes(self) -> Vec<(Node, Sender<Arc<Packet>>)> {
+        let offset = timestamp().saturating_sub(self.timestamp);
+        self.restore(offset)
+    }
+
+    fn restore(self, offset: u64) -> Vec<(Node, Sender<Arc<Packet>>)> {
+        let stakes: Arc<[u64]> = self.nodes.iter().map(|node| node.stake).collect();
+        self.nodes
+            .into_iter()
+            .map(|node| Node::from_checkpoint(node, offset, &stakes))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        crate::{
+            crds_value::CrdsValueType,
+            gossip::{make_gossip_nodes, Config, Trace},
+        },
+        rand::SeedableRng,
+        rand_chacha::ChaChaRng,
+    };
+
+    // Packet with timestamps cleared, since those are taken from wallclock.
+    fn strip_timestamps(packet: &Packet) -> Packet {
+        let mut packet = packet.clone();
+        match &mut packet {
+            Packet::Push {
+                wallclock,
+                timestamp,
+                ..
+            } => {
+                *wallclock = 0;
+                *timestamp = 0;
+            }
+            Packet::Prune { wallclock, .. } => *wallclock = 0,
+        }
+        packet
+    }
+
+    #[test]
+    fn test_checkpoint_round_trip() {
+        let config = Config {
+            trace: Some(Trace {
+                key: CrdsKey::new(NodeId(0), CrdsValueType::Vote, 0),
+                ordinal: 1,
+            }),
+            ..Config::new_for_tests()
+        };
+        let stakes = (1..=20).map(|k| (Pubkey::new_unique(), k * 1_000_000_000));
+        let mut rng = ChaChaRng::seed_from_u64(42);
+        let (mut nodes, senders): (Vec<Node>, Vec<_>) =
+            make_gossip_nodes(&mut rng, stakes, &config)
+                .into_iter()
+                .unzip();
+        let router = Router::new(config.packet_drop_rate, senders).unwrap();
+        for _ in 0..10 {
+            for node in &mut nodes {
+                node.run_gossip(&config, &router, &()).unwrap();
+            }
+        }
+        let checkpoint = Checkpoint::new(&mut nodes, &router).unwrap();
+        assert_eq!(checkpoint.num_nodes(), nodes.len());
+        assert!(checkpoint.nodes.iter().any(|node| !node.packets.is_empty()));
+        assert!(checkpoint.nodes.iter().any(|node| !node.trace.is_empty()));
+        let received_cache = ReceivedCache::new(
+            config.received_cache_capacity,
+            config.received_cache_min_num_upserts,
+            config.received_cache_entry_capacity,
+            config.received_cache_num_dups_threshold,
+            config.received_cache_scorer,
+        );
+        assert!(checkpoint
+            .nodes
+            .iter()
+            .all(|node| node.received_cache != received_cache));
+        // Packets in flight are retained by the original nodes.
+        let other = Checkpoint::new(&mut nodes, &router).unwrap();
+        for (node, other) in checkpoint.nodes.iter().zip(&other.nodes) {
+            assert_eq!(node.packets, other.packets);
+        }
+        let bytes = bincode::serialize(&checkpoint).unwrap();
+        let (mut restored, senders): (Vec<Node>, Vec<_>) =
+            bincode::deserialize::<Checkpoint>(&bytes)
+                .unwrap()
+                .restore(0)
+                .into_iter()
+                .unzip();
+        let restored_router = Router::new(config.packet_drop_rate, senders).unwrap();
+        let other = Checkpoint::new(&mut restored, &restored_router).unwrap();
+        for (node, other) in checkpoint.nodes.iter().zip(&other.nodes) {
+            assert_eq!(node.num_gossip_rounds, other.num_gossip_rounds);
+            assert_eq!(
+                (node.id, node.pubkey, node.stake),
+                (other.id, other.pubkey, other.stake)
+            );
+            assert_eq!(node.stakes, other.stakes);
+            assert_eq!(node.next_stakes, other.next_stakes);
+            assert_eq!(node.table, other.table);
+            assert_eq!(node.entrypoints, other.entrypoints);
+            assert_eq!(node.discovery_round, other.discovery_round);
+            assert_eq!(node.active_set, other.active_set);
+            assert!(node.received_cache == other.received_cache);
+            assert_eq!(format!("{:?}", node.stats), format!("{:?}", other.stats));
+            assert_eq!(format!("{:?}", node.trace), format!("{:?}", other.trace));
+            assert_eq!(node.rng_seed, other.rng_seed);
+            assert_eq!(node.rng_word_pos, other.rng_word_pos);
+            assert_eq!(node.packets, other.packets);
+        }
+        // Restored nodes run the next round the same as the original nodes,
+        // apart from timestamps taken from wallclock.
+        for (node, other) in nodes.iter_mut().zip(&mut restored) {
+            node.run_gossip(&config, &router, &()).unwrap();
+            other.run_gossip(&config, &restored_router, &()).unwrap();
+        }
+        let checkpoint = Checkpoint::new(&mut nodes, &router).unwrap();
+        let other = Checkpoint::new(&mut restored, &restored_router).unwrap();
+        for (node, other) in checkpoint.nodes.iter().zip(&other.nodes) {
+            assert_eq!(node.num_gossip_rounds, other.num_gossip_rounds);
+            let get_ordinals = |node: &NodeCheckpoint| -> HashMap<CrdsKey, u64> {
+                node.table
+                    .iter()
+                    .map(|(key, entry)| (*key, entry.ordinal()))
+                    .collect()
+            };
+            assert_eq!(get_ordinals(node), get_ordinals(other));
+            assert_eq!(node.active_set, other.active_set);
+            assert!(node.received_cache == other.received_cache);
+            assert_eq!(format!("{:?}", node.stats), format!("{:?}", other.stats));
+            assert_eq!(node.rng_seed, other.rng_seed);
+            assert_eq!(node.rng_word_pos, other.rng_word_pos);
+            assert!(node
+                .packets
+                .iter()
+                .map(strip_timestamps)
+                .eq(other.packets.iter().map(strip_timestamps)));
+        }
+    }
+}
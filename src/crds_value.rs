@@ -0,0 +1,235 @@
+use {
+    crate::Error,
+    serde::{Deserialize, Serialize},
+    std::{
+        fmt::{self, Display, Formatter},
+        str::FromStr,
+    },
+};
+
+pub const NUM_CRDS_VALUE_TYPES: usize = 9;
+
+/// Types of crds values carried by gossip.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize)]
+pub enum CrdsValueType {
+    ContactInfo,
+    Vote,
+    LowestSlot,
+    EpochSlots,
+    SnapshotHashes,
+    AccountsHashes,
+    NodeInstance,
+    Version,
+    DuplicateShred,
+}
+
+/// Number, size and update frequency of crds values of one type generated
+/// by each node.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct CrdsValueParams {
+    // Number of distinct values of this type per node, e.g. vote slots.
+    pub count: usize,
+    // Serialized size in bytes.
+    pub size: usize,
+    // Number of values of this type refreshed by each node in each gossip
+    // round.
+    pub refresh_rate: f64,
+}
+
+/// Crds values generated by each node, indexed by CrdsValueType.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct CrdsValueModel([CrdsValueParams; NUM_CRDS_VALUE_TYPES]);
+
+impl CrdsValueType {
+    pub const ALL: [Self; NUM_CRDS_VALUE_TYPES] = [
+        Self::ContactInfo,
+        Self::Vote,
+        Self::LowestSlot,
+        Self::EpochSlots,
+        Self::SnapshotHashes,
+        Self::AccountsHashes,
+        Self::NodeInstance,
+        Self::Version,
+        Self::DuplicateShred,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::ContactInfo => "contact-info",
+            Self::Vote => "vote",
+            Self::LowestSlot => "lowest-slot",
+            Self::EpochSlots => "epoch-slots",
+            Self::SnapshotHashes => "snapshot-hashes",
+            Self::AccountsHashes => "accounts-hashes",
+            Self::NodeInstance => "node-instance",
+            Self::Version => "version",
+            Self::DuplicateShred => "duplicate-shred",
+        }
+    }
+
+    fn index(self) -> usize {
+        self as usize
+    }
+}
+
+impl Display for CrdsValueType {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.pad(self.name())
+    }
+}
+
+impl FromStr for CrdsValueType {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .into_iter()
+            .find(|kind| kind.name() == s)
+            .ok_or_else(|| Error::InvalidCrdsValues(format!("invalid type: {s}")))
+    }
+}
+
+impl CrdsValueModel {
+    /// Only values of the given types, with no values of other types.
+    pub fn new<I>(params: I) -> Self
+    where
+        I: IntoIterator<Item = (CrdsValueType, CrdsValueParams)>,
+    {
+        params
+            .into_iter()
+            .fold(Self::default(), |model, (kind, params)| {
+                model.with(kind, params)
+            })
+    }
+
+    /// Approximates values on mainnet, where gossip rounds run every 100ms
+    /// and slots are 400ms. Contact info and the values refreshed along with
+    /// it are pushed every 7.5s, votes every slot, snapshot and accounts
+    /// hashes every 100 slots, while duplicate shreds are rare.
+    pub fn mainnet() -> Self {
+        let params = |count, size, refresh_rate| CrdsValueParams {
+            count,
+            size,
+            refresh_rate,
+        };
+        Self([
+            params(1, 250, 1.0 / 75.0), // ContactInfo
+            params(32, 600, 0.25),      // Vote
+            params(1, 100, 0.001),      // LowestSlot
+            params(255, 1000, 0.01),    // EpochSlots
+            params(1, 700, 0.0025),     // SnapshotHashes
+            params(1, 700, 0.0025),     // AccountsHashes
+            params(1, 100, 1.0 / 75.0), // NodeInstance
+            params(1, 120, 1.0 / 75.0), // Version
+            params(512, 1232, 0.000_1), // DuplicateShred
+        ])
+    }
+
+    /// Replaces the parameters of the given type.
+    pub fn with(mut self, kind: CrdsValueType, params: CrdsValueParams) -> Self {
+        self.0[kind.index()] = params;
+        self
+    }
+
+    pub fn get(&self, kind: CrdsValueType) -> &CrdsValueParams {
+        &self.0[kind.index()]
+    }
+
+    /// Returns types which have values, along with their parameters.
+    pub fn iter(&self) -> impl Iterator<Item = (CrdsValueType, &CrdsValueParams)> {
+        CrdsValueType::ALL
+            .into_iter()
+            .zip(&self.0)
+            .filter(|(_kind, params)| params.count != 0)
+    }
+
+    /// Total number of distinct values per node.
+    pub fn num_values(&self) -> usize {
+        self.0.iter().map(|params| params.count).sum()
+    }
+}
+
+impl FromStr for CrdsValueModel {
+    type Err = Error;
+
+    // Parses a comma separated list, applied in order, of either "mainnet"
+    // or "<type>:<count>:<size>:<refresh rate>", e.g. "mainnet,vote:32:600:1"
+    // or "vote:8:600:1" for votes only.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut model = Self::default();
+        for item in s.split(',') {
+            if item == "mainnet" {
+                model = Self::mainnet();
+                continue;
+            }
+            let err = || Error::InvalidCrdsValues(item.to_string());
+            let (kind, params) = item.split_once(':').ok_or_else(err)?;
+            let params: Vec<&str> = params.split(':').collect();
+            let [count, size, refresh_rate] = params[..] else {
+                return Err(err());
+            };
+            let params = CrdsValueParams {
+                count: count.parse().map_err(|_| err())?,
+                size: size.parse().map_err(|_| err())?,
+                refresh_rate: refresh_rate.parse().map_err(|_| err())?,
+            };
+            // Types with values should be refreshed, and vice versa.
+            let is_valid = if params.count == 0 {
+                params.refresh_rate == 0.0
+            } else {
+                params.refresh_rate > 0.0 && params.refresh_rate.is_finite()
+            };
+            if !is_valid {
+                return Err(err());
+            }
+            model = model.with(kind.parse()?, params);
+        }
+        if model.num_values() == 0 {
+            return Err(Error::InvalidCrdsValues(format!("no values: {s}")));
+        }
+        Ok(model)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_crds_value_model_from_str() {
+        for kind in CrdsValueType::ALL {
+            assert_eq!(kind.name().parse::<CrdsValueType>().unwrap(), kind);
+        }
+        assert_eq!(
+            "mainnet".parse::<CrdsValueModel>().unwrap(),
+            CrdsValueModel::mainnet()
+        );
+        let vote = CrdsValueParams {
+            count: 8,
+            size: 600,
+            refresh_rate: 1.5,
+        };
+        let model: CrdsValueModel = "vote:8:600:1.5".parse().unwrap();
+        assert_eq!(model, CrdsValueModel::new([(CrdsValueType::Vote, vote)]));
+        assert!(model.iter().eq([(CrdsValueType::Vote, &vote)]));
+        assert_eq!(model.num_values(), 8);
+        let model: CrdsValueModel = "mainnet,vote:8:600:1.5".parse().unwrap();
+        assert_eq!(
+            model,
+            CrdsValueModel::mainnet().with(CrdsValueType::Vote, vote)
+        );
+        assert_eq!(model.get(CrdsValueType::ContactInfo).count, 1);
+        for s in [
+            "",
+            "testnet",
+            "vote:8:600",
+            "vote:8:600:1.5:1",
+            "ballot:8:600:1.5",
+            "vote:8:600:-1",
+            "vote:0:600:1",
+            "vote:0:600:0",
+        ] {
+            assert!(s.parse::<CrdsValueModel>().is_err(), "{s}");
+        }
+    }
+}
@@ -1,8 +1,19 @@
 use {
-    clap::{crate_description, crate_name, App, Arg},
-    log::info,
-    rand::Rng,
-    std::collections::VecDeque,
+    clap::{crate_description, crate_name, App, Arg, ArgGroup, ErrorKind},
+    cluster_mocks::{
+        gossip::get_cluster_stakes,
+        push_active_set::{
+            get_sampling_weight, get_stake_bucket, NUM_PUSH_ACTIVE_SET_ENTRIES,
+            PUSH_ACTIVE_SET_FANOUT_MULTIPLE,
+        },
+        received_cache::{ReceivedCache, ReceivedCacheScorer},
+        stakes::{make_synthetic_stakes, read_stakes, write_stakes, StakeDistribution},
+        NodeId,
+    },
+    log::{info, warn},
+    rand::{distributions::WeightedIndex, prelude::Distribution, Rng},
+    solana_client::rpc_client::RpcClient,
+    std::{cmp::Reverse, collections::VecDeque, time::Duration},
 };
 
 #[derive(Debug)]
@@ -13,18 +24,471 @@ struct Config {
     bounce_back: f64,
     cluster_size: usize,
     num_rounds: usize,
+    // How the origin of the broadcast is selected in each round.
+    origin: OriginSelection,
+    // Number of least covered nodes to report.
+    num_worst_nodes: usize,
+    // If set, nodes maintain persistent active sets and prune inbound peers
+    // across rounds.
+    prune: Option<PruneConfig>,
 }
 
-fn run_fanout<R: Rng>(rng: &mut R, config: &Config) {
+#[derive(Clone, Copy, Debug)]
+enum OriginSelection {
+    // Always broadcast from node 0, i.e. the node with the most stake.
+    Fixed,
+    // Broadcast from a uniformly random node in each round.
+    Random,
+    // Broadcast from a random node in each round, weighted by stake.
+    StakeWeighted,
+}
+
+#[derive(Debug)]
+struct PruneConfig {
+    // Number of rounds between push active set rotations.
+    rotate_active_set_rounds: usize,
+    // Min ingress number of nodes to keep when pruning.
+    min_ingress_nodes: usize,
+    // Fraction of min stake of {this node, origin} to keep when pruning.
+    stake_threshold: f64,
+    // Minimum number of upserts before pruning.
+    min_num_upserts: usize,
+    // Max number of inbound peers tracked for the origin.
+    entry_capacity: usize,
+    // Number of duplicates before which a message is counted as timely.
+    num_dups_threshold: usize,
+    // Number of rounds aggregated in each reported row.
+    report_interval: usize,
+}
+
+// Samples gossip peers with the same weights as PushActiveSet::rotate.
+struct PeerSampler<'a> {
+    stakes: &'a [u64],
+    // Cumulative sampling weights of nodes for each active-set entry.
+    weights: Vec<Vec<u64>>,
+}
+
+impl<'a> PeerSampler<'a> {
+    fn new(stakes: &'a [u64]) -> Self {
+        let buckets: Vec<_> = stakes
+            .iter()
+            .map(|stake| get_stake_bucket(Some(stake)))
+            .collect();
+        let weights = (0..NUM_PUSH_ACTIVE_SET_ENTRIES)
+            .map(|k| {
+                buckets
+                    .iter()
+                    .scan(0u64, |acc, &bucket| {
+                        *acc += get_sampling_weight(k, bucket);
+                        Some(*acc)
+                    })
+                    .collect()
+            })
+            .collect();
+        Self { stakes, weights }
+    }
+
+    // Samples distinct peers which the node pushes crds values from the
+    // origin to. Sampling one at a time and rejecting repeats is equivalent
+    // to taking the first entries of a weighted shuffle.
+    fn sample<R: Rng>(
+        &self,
+        rng: &mut R,
+        node: usize,
+        origin: usize,
+        size: usize,
+        peers: &mut Vec<usize>,
+    ) {
+        let stake = self.stakes[node].min(self.stakes[origin]);
+        let weights = &self.weights[get_stake_bucket(Some(&stake))];
+        let size = size.min(self.stakes.len() - 1);
+        while peers.len() < size {
+            let weight = rng.gen_range(0, weights[weights.len() - 1]);
+            let peer = weights.partition_point(|&w| w <= weight);
+            if peer != node && !peers.contains(&peer) {
+                peers.push(peer);
+            }
+        }
+    }
+}
+
+fn run_fanout<R: Rng>(rng: &mut R, config: &Config, stakes: Option<&[u64]>) {
+    let sampler = stakes.map(PeerSampler::new);
+    let origins = match config.origin {
+        OriginSelection::Fixed | OriginSelection::Random => None,
+        OriginSelection::StakeWeighted => {
+            let stakes = stakes.expect("stake-weighted origin requires stakes");
+            Some(WeightedIndex::new(stakes).unwrap())
+        }
+    };
+    // Hop count at which each node is reached in the current round.
+    let mut hops = vec![usize::MAX; config.cluster_size];
+    // Distribution of hop counts across all rounds.
+    let mut num_hops = Vec::<usize>::new();
+    // Number of rounds with incomplete coverage, and min coverage.
+    let mut num_incomplete_rounds: usize = 0;
+    let mut min_num_seen = config.cluster_size;
+    // Per node number of rounds as the origin and sum of hop counts.
+    let mut node_origin = vec![0usize; config.cluster_size];
+    let mut node_hops = vec![0usize; config.cluster_size];
     let mut queue = VecDeque::with_capacity(config.cluster_size);
     let mut seen = vec![false; config.cluster_size];
     let mut nodes: Vec<_> = (0..config.cluster_size).collect();
+    let mut peers = Vec::new();
     let mut num_packets: usize = 0;
     let mut num_outdated: usize = 0;
     let mut num_seen: usize = 0;
+    // Per node number of received packets, outdated packets and rounds
+    // the node was reached.
+    let mut node_packets = vec![0usize; config.cluster_size];
+    let mut node_outdated = vec![0usize; config.cluster_size];
+    let mut node_seen = vec![0usize; config.cluster_size];
     for _ in 0..config.num_rounds {
+        let origin = match (config.origin, &origins) {
+            (_, Some(origins)) => origins.sample(rng),
+            (OriginSelection::Random, None) => rng.gen_range(0, config.cluster_size),
+            (_, None) => 0,
+        };
+        node_origin[origin] += 1;
         queue.clear();
         seen.fill(false);
+        hops.fill(usize::MAX);
+        seen[origin] = true;
+        hops[origin] = 0;
+        queue.push_back(origin);
+        while let Some(node) = queue.pop_front() {
+            let gossip_push_fanout = if node == origin {
+                config.gossip_push_wide_fanout
+            } else {
+                config.gossip_push_fanout
+            };
+            let gossip_push_fanout =
+                gossip_push_fanout as usize + rng.gen_bool(gossip_push_fanout % 1.0) as usize;
+            peers.clear();
+            match &sampler {
+                None => {
+                    for i in 0..gossip_push_fanout {
+                        let j = rng.gen_range(i, config.cluster_size);
+                        nodes.swap(i, j);
+                    }
+                    let others = nodes[..gossip_push_fanout].iter();
+                    peers.extend(others.filter(|&&other| other != node));
+                }
+                Some(sampler) => sampler.sample(rng, node, origin, gossip_push_fanout, &mut peers),
+            }
+            for &other in &peers {
+                num_packets += 1;
+                node_packets[other] += 1;
+                if seen[other] {
+                    num_outdated += 1;
+                    node_outdated[other] += 1;
+                    if rng.gen_bool(config.bounce_back) {
+                        queue.push_back(other);
+                    }
+                } else {
+                    seen[other] = true;
+                    hops[other] = hops[node] + 1;
+                    queue.push_back(other);
+                }
+            }
+        }
+        let num_seen_round = seen.iter().filter(|k| **k).count();
+        num_seen += num_seen_round;
+        if num_seen_round < config.cluster_size {
+            num_incomplete_rounds += 1;
+        }
+        min_num_seen = min_num_seen.min(num_seen_round);
+        for (node, &hop) in hops.iter().enumerate() {
+            if hop == usize::MAX {
+                continue;
+            }
+            node_seen[node] += 1;
+            node_hops[node] += hop;
+            if num_hops.len() <= hop {
+                num_hops.resize(hop + 1, 0);
+            }
+            num_hops[hop] += 1;
+        }
+    }
+    let num_rounds = config.num_rounds as f64;
+    let simulated = FanoutStats {
+        packets_node: num_packets as f64 / config.cluster_size as f64 / num_rounds,
+        outdated: num_outdated as f64 * 100.0 / num_packets as f64,
+        waste: num_outdated as f64 / (num_packets - num_outdated) as f64,
+        propagation: num_seen as f64 * 100.0 / config.cluster_size as f64 / num_rounds,
+    };
+    if sampler.is_some() {
+        warn!("push epidemic model assumes uniform peer sampling, unlike stake-weighted peers");
+    }
+    print_fanout_stats(Some(&simulated), get_push_epidemic_stats(config).as_ref());
+    let incomplete = num_incomplete_rounds as f64 * 100.0 / num_rounds;
+    println!("incomplete:   {incomplete:.2}% of rounds");
+    let min_coverage = min_num_seen as f64 * 100.0 / config.cluster_size as f64;
+    println!("min coverage: {min_coverage:.2}%");
+    print_hops_distribution(&num_hops);
+    print_worst_nodes(config, stakes, &node_origin, &node_seen, &node_hops);
+    if let Some(stakes) = stakes {
+        print_bucket_stats(config, stakes, &node_packets, &node_outdated, &node_seen);
+    }
+}
+
+// Per round traffic and coverage of a broadcast.
+struct FanoutStats {
+    packets_node: f64,
+    outdated: f64, // %
+    waste: f64,
+    propagation: f64, // %
+}
+
+// Returns expected stats under the mean-field push epidemic model, where
+// each newly informed node pushes to gossip_push_fanout distinct peers
+// sampled uniformly at random, and each duplicate is re-pushed with the
+// bounce_back probability. The final number of informed nodes I solves:
+//     I = 1 + (n - 1) * (1 - (1 - f_w / (n - 1)) * (1 - f / (n - 1))^(I - 1 + B))
+// where B is the expected number of bounced duplicates.
+// Returns None if bounced duplicates make the process supercritical.
+fn get_push_epidemic_stats(config: &Config) -> Option<FanoutStats> {
+    const MAX_ITERATIONS: usize = 1000;
+    let n = config.cluster_size as f64;
+    let f = config.gossip_push_fanout.min(n - 1.0);
+    let f_w = config.gossip_push_wide_fanout.min(n - 1.0);
+    let b = config.bounce_back;
+    if n < 2.0 || b * f >= 1.0 {
+        return None;
+    }
+    // Number of bounced duplicates given I informed nodes, from:
+    //     P = f_w + f * (I - 1 + B)
+    //     B = b * (P - (I - 1))
+    let get_num_bounces = |i: f64| b * (f_w + (f - 1.0) * (i - 1.0)) / (1.0 - b * f);
+    // Iterating from I = n converges to the largest fixed point.
+    let mut i = n;
+    for _ in 0..MAX_ITERATIONS {
+        let num_senders = i - 1.0 + get_num_bounces(i);
+        let miss = (1.0 - f_w / (n - 1.0)) * (1.0 - f / (n - 1.0)).powf(num_senders);
+        let next = 1.0 + (n - 1.0) * (1.0 - miss);
+        let done = (next - i).abs() < 1e-9;
+        i = next;
+        if done {
+            break;
+        }
+    }
+    let num_packets = f_w + f * (i - 1.0 + get_num_bounces(i));
+    let num_outdated = num_packets - (i - 1.0);
+    Some(FanoutStats {
+        packets_node: num_packets / n,
+        outdated: num_outdated * 100.0 / num_packets,
+        waste: num_outdated / (i - 1.0),
+        propagation: i * 100.0 / n,
+    })
+}
+
+// Prints simulated stats next to the push epidemic model, and the relative
+// deviation of simulated values from the model.
+fn print_fanout_stats(simulated: Option<&FanoutStats>, model: Option<&FanoutStats>) {
+    let get_values =
+        |get_value: fn(&FanoutStats) -> f64| (simulated.map(get_value), model.map(get_value));
+    let rows = [
+        ("packets/node", get_values(|stats| stats.packets_node)),
+        ("outdated %", get_values(|stats| stats.outdated)),
+        ("waste", get_values(|stats| stats.waste)),
+        ("propagation %", get_values(|stats| stats.propagation)),
+    ];
+    let format = |value: Option<f64>| match value {
+        None => format!("{:>9}", "-"),
+        Some(value) => format!("{value:9.2}"),
+    };
+    println!("              | simulated |   model   | deviation");
+    println!("--------------------------------------------------");
+    for (name, (simulated, model)) in rows {
+        let deviation = match (simulated, model) {
+            (Some(simulated), Some(model)) => {
+                format!("{:+8.2}%", (simulated - model) * 100.0 / model)
+            }
+            _ => format!("{:>9}", "-"),
+        };
+        println!(
+            "{name:13} | {} | {} | {deviation}",
+            format(simulated),
+            format(model),
+        );
+    }
+}
+
+// Prints distribution of the number of hops at which nodes are reached,
+// excluding the origin.
+fn print_hops_distribution(num_hops: &[usize]) {
+    let total: usize = num_hops.iter().skip(1).sum();
+    let mut cumulative = 0;
+    println!("hops |    nodes |      % | cumulative");
+    println!("-------------------------------------");
+    for (hop, &count) in num_hops.iter().enumerate().skip(1) {
+        cumulative += count;
+        println!(
+            "{hop:4} | {count:8} | {:5.2}% | {:9.2}%",
+            count as f64 * 100.0 / total.max(1) as f64,
+            cumulative as f64 * 100.0 / total.max(1) as f64,
+        );
+    }
+}
+
+// Prints nodes which are reached in the fewest rounds, excluding rounds
+// where the node itself is the origin.
+fn print_worst_nodes(
+    config: &Config,
+    stakes: Option<&[u64]>,
+    node_origin: &[usize],
+    node_seen: &[usize],
+    node_hops: &[usize],
+) {
+    let active_stake: u64 = stakes.map(|stakes| stakes.iter().sum()).unwrap_or_default();
+    let get_coverage = |node: usize| {
+        let num_rounds = config.num_rounds - node_origin[node];
+        let num_seen = node_seen[node] - node_origin[node];
+        num_seen as f64 / num_rounds.max(1) as f64
+    };
+    let mut nodes: Vec<_> = (0..config.cluster_size)
+        .filter(|&node| node_origin[node] < config.num_rounds)
+        .collect();
+    nodes.sort_unstable_by(|&a, &b| get_coverage(a).total_cmp(&get_coverage(b)));
+    println!("node  |  stake  | coverage | hops");
+    println!("----------------------------------");
+    for node in nodes.into_iter().take(config.num_worst_nodes) {
+        let stake = stakes.map(|stakes| stakes[node]).unwrap_or_default();
+        println!(
+            "{node:5} | {:6.3}% | {:7.2}% | {:4.2}",
+            stake as f64 * 100.0 / active_stake.max(1) as f64,
+            get_coverage(node) * 100.0,
+            node_hops[node] as f64 / node_seen[node].max(1) as f64,
+        );
+    }
+}
+
+// Prints propagation and waste aggregated by stake bucket of receiving nodes.
+fn print_bucket_stats(
+    config: &Config,
+    stakes: &[u64],
+    node_packets: &[usize],
+    node_outdated: &[usize],
+    node_seen: &[usize],
+) {
+    #[derive(Default)]
+    struct BucketStats {
+        num_nodes: usize,
+        stake: u64,
+        num_packets: usize,
+        num_outdated: usize,
+        num_seen: usize,
+    }
+    let mut buckets: Vec<_> = std::iter::repeat_with(BucketStats::default)
+        .take(NUM_PUSH_ACTIVE_SET_ENTRIES)
+        .collect();
+    for (node, stake) in stakes.iter().enumerate() {
+        let bucket = &mut buckets[get_stake_bucket(Some(stake))];
+        bucket.num_nodes += 1;
+        bucket.stake += stake;
+        bucket.num_packets += node_packets[node];
+        bucket.num_outdated += node_outdated[node];
+        bucket.num_seen += node_seen[node];
+    }
+    let active_stake: u64 = stakes.iter().sum();
+    let num_rounds = config.num_rounds as f64;
+    println!("bucket | nodes |  stake  | packets/node | outdated | waste | propagation");
+    println!("--------------------------------------------------------------------------");
+    for (k, bucket) in buckets.iter().enumerate() {
+        if bucket.num_nodes == 0 {
+            continue;
+        }
+        let num_nodes = bucket.num_nodes as f64;
+        let num_useful = bucket.num_packets - bucket.num_outdated;
+        println!(
+            "{k:6} | {:5} | {:6.2}% | {:12.2} | {:7.2}% | {:5.1} | {:10.2}%",
+            bucket.num_nodes,
+            bucket.stake as f64 * 100.0 / active_stake.max(1) as f64,
+            bucket.num_packets as f64 / num_nodes / num_rounds,
+            bucket.num_outdated as f64 * 100.0 / bucket.num_packets.max(1) as f64,
+            bucket.num_outdated as f64 / num_useful.max(1) as f64,
+            bucket.num_seen as f64 * 100.0 / num_nodes / num_rounds,
+        );
+    }
+}
+
+// Same as PushActiveSetEntry::rotate: inserts one new peer and drops the
+// oldest one, discarding its prunes.
+fn rotate_active_set<R: Rng>(
+    rng: &mut R,
+    sampler: &PeerSampler,
+    node: usize,
+    size: usize,
+    active_set: &mut VecDeque<(/*peer:*/ usize, /*pruned:*/ bool)>,
+    peers: &mut Vec<usize>,
+) {
+    peers.clear();
+    sampler.sample(rng, node, /*origin:*/ 0, size + 1, peers);
+    for &peer in peers.iter() {
+        if active_set.len() > size {
+            break;
+        }
+        if active_set.iter().all(|&(other, _)| other != peer) {
+            active_set.push_back((peer, false));
+        }
+    }
+    while active_set.len() > size {
+        active_set.pop_front();
+    }
+}
+
+// Simulates rounds of gossip from origin 0 where each node pushes to its
+// persistent active set and prunes inbound peers which deliver duplicates,
+// reporting how the traffic evolves towards steady state.
+fn run_fanout_with_prunes<R: Rng>(
+    rng: &mut R,
+    config: &Config,
+    prune_config: &PruneConfig,
+    stakes: Option<&[u64]>,
+) {
+    let cluster_size = config.cluster_size;
+    // Without stakes all nodes fall into the same stake bucket, and the
+    // sampler reduces to uniform sampling.
+    let zero_stakes = vec![0u64; cluster_size];
+    let stakes = stakes.unwrap_or(&zero_stakes);
+    let sampler = PeerSampler::new(stakes);
+    let active_set_size = config.gossip_push_fanout as usize * PUSH_ACTIVE_SET_FANOUT_MULTIPLE;
+    let mut active_sets = vec![VecDeque::new(); cluster_size];
+    // Each node only tracks the origin. Deliveries are not timed, so
+    // messages are scored by their number of duplicates.
+    let mut caches: Vec<_> = std::iter::repeat_with(|| {
+        ReceivedCache::new(
+            1, // capacity
+            prune_config.min_num_upserts,
+            prune_config.entry_capacity,
+            prune_config.num_dups_threshold,
+            ReceivedCacheScorer::NumDups,
+        )
+    })
+    .take(cluster_size)
+    .collect();
+    let mut peers = Vec::new();
+    for (node, active_set) in active_sets.iter_mut().enumerate() {
+        rotate_active_set(rng, &sampler, node, active_set_size, active_set, &mut peers);
+    }
+    let mut queue = VecDeque::with_capacity(cluster_size);
+    let mut seen = vec![false; cluster_size];
+    let mut num_dups = vec![0usize; cluster_size];
+    let mut num_packets: usize = 0;
+    let mut num_outdated: usize = 0;
+    let mut num_seen: usize = 0;
+    println!("rounds        | packets/node | outdated | waste | propagation | pruned");
+    println!("--------------------------------------------------------------------");
+    for round in 0..config.num_rounds {
+        // Stagger rotations across nodes.
+        for (node, active_set) in active_sets.iter_mut().enumerate() {
+            if round > 0 && (round + node) % prune_config.rotate_active_set_rounds == 0 {
+                rotate_active_set(rng, &sampler, node, active_set_size, active_set, &mut peers);
+            }
+        }
+        queue.clear();
+        seen.fill(false);
+        num_dups.fill(0);
         seen[0] = true;
         queue.push_back(0);
         while let Some(node) = queue.pop_front() {
@@ -35,17 +499,19 @@ fn run_fanout<R: Rng>(rng: &mut R, config: &Config) {
             };
             let gossip_push_fanout =
                 gossip_push_fanout as usize + rng.gen_bool(gossip_push_fanout % 1.0) as usize;
-            for i in 0..gossip_push_fanout {
-                let j = rng.gen_range(i, config.cluster_size);
-                nodes.swap(i, j);
-            }
-            for &other in &nodes[..gossip_push_fanout] {
-                if other == node {
-                    continue;
-                }
+            peers.clear();
+            peers.extend(
+                active_sets[node]
+                    .iter()
+                    .filter(|(_, pruned)| !pruned)
+                    .map(|&(peer, _)| peer)
+                    .take(gossip_push_fanout),
+            );
+            for &other in &peers {
                 num_packets += 1;
                 if seen[other] {
                     num_outdated += 1;
+                    num_dups[other] += 1;
                     if rng.gen_bool(config.bounce_back) {
                         queue.push_back(other);
                     }
@@ -53,19 +519,85 @@ fn run_fanout<R: Rng>(rng: &mut R, config: &Config) {
                     seen[other] = true;
                     queue.push_back(other);
                 }
+                caches[other].record(
+                    NodeId(0), // origin
+                    NodeId::from(node),
+                    num_dups[other],
+                    Duration::ZERO, // delay
+                );
             }
         }
         num_seen += seen.iter().filter(|k| **k).count();
+        // Send prunes for the origin to inbound peers.
+        for (node, cache) in caches.iter_mut().enumerate() {
+            let Some(decision) = cache.prune(
+                NodeId::from(node),
+                NodeId(0), // origin
+                prune_config.stake_threshold,
+                prune_config.min_ingress_nodes,
+                stakes,
+            ) else {
+                continue;
+            };
+            for peer in decision.pruned() {
+                if let Some(entry) = active_sets[peer.index()]
+                    .iter_mut()
+                    .find(|(other, _)| *other == node)
+                {
+                    entry.1 = true;
+                }
+            }
+        }
+        if (round + 1) % prune_config.report_interval == 0 || round + 1 == config.num_rounds {
+            let num_rounds = (round % prune_config.report_interval + 1) as f64;
+            let num_links: usize = active_sets.iter().map(VecDeque::len).sum();
+            let num_pruned = active_sets
+                .iter()
+                .flatten()
+                .filter(|(_, pruned)| *pruned)
+                .count();
+            println!(
+                "{:6}-{:6} | {:12.2} | {:7.2}% | {:5.2} | {:10.2}% | {:5.2}%",
+                round + 1 - num_rounds as usize,
+                round + 1,
+                num_packets as f64 / cluster_size as f64 / num_rounds,
+                num_outdated as f64 * 100.0 / num_packets.max(1) as f64,
+                num_outdated as f64 / (num_packets - num_outdated).max(1) as f64,
+                num_seen as f64 * 100.0 / cluster_size as f64 / num_rounds,
+                num_pruned as f64 * 100.0 / num_links.max(1) as f64,
+            );
+            num_packets = 0;
+            num_outdated = 0;
+            num_seen = 0;
+        }
     }
-    let num_rounds = config.num_rounds as f64;
-    let packets_node = num_packets as f64 / config.cluster_size as f64 / num_rounds;
-    println!("packets/node: {packets_node:.0}");
-    let outdated = num_outdated as f64 * 100.0 / num_packets as f64;
-    println!("outdated:     {outdated:.2}%");
-    let waste = num_outdated as f64 / (num_packets - num_outdated) as f64;
-    println!("waste:        {waste:.1}");
-    let propagation = num_seen as f64 * 100.0 / config.cluster_size as f64 / num_rounds;
-    println!("propagation:  {propagation:.2}%");
+}
+
+// Loads cluster stakes from a snapshot file, json rpc or a synthetic
+// distribution, sorted by stake in descending order.
+fn get_stakes<R: Rng>(
+    rng: &mut R,
+    matches: &clap::ArgMatches,
+    cluster_size: usize,
+) -> Option<Vec<u64>> {
+    let stakes = if let Some(path) = matches.value_of("stakes_file") {
+        read_stakes(path).unwrap()
+    } else if let Some(json_rpc_url) = matches.value_of("json_rpc_url") {
+        let json_rpc_url = cluster_mocks::get_json_rpc_url(json_rpc_url);
+        info!("json_rpc_url: {}", json_rpc_url);
+        get_cluster_stakes(&RpcClient::new(json_rpc_url)).unwrap()
+    } else if matches.is_present("stake_distribution") {
+        let distribution: StakeDistribution = matches.value_of_t_or_exit("stake_distribution");
+        make_synthetic_stakes(rng, cluster_size, distribution)
+    } else {
+        return None;
+    };
+    if let Some(path) = matches.value_of("save_stakes") {
+        write_stakes(path, &stakes).unwrap();
+    }
+    let mut stakes: Vec<u64> = stakes.into_iter().map(|(_pubkey, stake)| stake).collect();
+    stakes.sort_unstable_by_key(|&stake| Reverse(stake));
+    Some(stakes)
 }
 
 fn main() {
@@ -110,7 +642,124 @@ fn main() {
                 .default_value("10000")
                 .help("number of rounds to simulate"),
         )
+        .arg(
+            Arg::with_name("origin")
+                .long("origin")
+                .takes_value(true)
+                .possible_values(["fixed", "random", "stake-weighted"])
+                .default_value("fixed")
+                .requires_if("stake-weighted", "stakes_source")
+                .help("how the origin is selected in each round"),
+        )
+        .arg(
+            Arg::with_name("num_worst_nodes")
+                .long("num-worst-nodes")
+                .takes_value(true)
+                .default_value("10")
+                .help("number of least covered nodes to report"),
+        )
+        .arg(
+            Arg::with_name("analytic_only")
+                .long("analytic-only")
+                // The model assumes uniform peer sampling without prunes.
+                .conflicts_with_all(&["stakes_source", "prune"])
+                .help("only print push epidemic model predictions, skipping simulation"),
+        )
+        .arg(
+            Arg::with_name("prune")
+                .long("prune")
+                .help("model pruning with persistent active sets across rounds"),
+        )
+        .arg(
+            Arg::with_name("rotate_active_set_rounds")
+                .long("rotate-active-set-rounds")
+                .takes_value(true)
+                .default_value("35")
+                .help("number of rounds between push active set rotations"),
+        )
+        .arg(
+            Arg::with_name("min_ingress_nodes")
+                .long("min-ingress-nodes")
+                .takes_value(true)
+                .default_value("3")
+                .help("min ingress number of nodes to keep when pruning"),
+        )
+        .arg(
+            Arg::with_name("prune_stake_threshold")
+                .long("prune-stake-threshold")
+                .takes_value(true)
+                .default_value("0.15")
+                .help("fraction of min stake of {this node, origin} to keep when pruning"),
+        )
+        .arg(
+            Arg::with_name("min_num_upserts")
+                .long("min-num-upserts")
+                .takes_value(true)
+                .default_value("20")
+                .help("min number of upserts before pruning"),
+        )
+        .arg(
+            Arg::with_name("received_cache_entry_capacity")
+                .long("received-cache-entry-capacity")
+                .takes_value(true)
+                .default_value("50")
+                .help("max number of inbound peers tracked when pruning"),
+        )
+        .arg(
+            Arg::with_name("num_dups_threshold")
+                .long("num-dups-threshold")
+                .takes_value(true)
+                .default_value("2")
+                .help("number of duplicates before which a message is counted as timely"),
+        )
+        .arg(
+            Arg::with_name("report_interval")
+                .long("report-interval")
+                .takes_value(true)
+                .default_value("500")
+                .help("number of rounds aggregated in each reported row"),
+        )
+        .arg(
+            Arg::with_name("json_rpc_url")
+                .long("url")
+                .value_name("URL_OR_MONIKER")
+                .takes_value(true)
+                .help("load stakes from solana's json rpc url"),
+        )
+        .arg(
+            Arg::with_name("stakes_file")
+                .long("stakes-file")
+                .takes_value(true)
+                .conflicts_with("json_rpc_url")
+                .help("load stakes from a snapshot file"),
+        )
+        .arg(
+            Arg::with_name("stake_distribution")
+                .long("stake-distribution")
+                .takes_value(true)
+                .conflicts_with_all(&["json_rpc_url", "stakes_file"])
+                .help("synthetic stake distribution: equal, uniform or pareto:<shape>"),
+        )
+        .arg(
+            Arg::with_name("save_stakes")
+                .long("save-stakes")
+                .takes_value(true)
+                .help("save loaded stakes to a snapshot file"),
+        )
+        .group(ArgGroup::new("stakes_source").args(&[
+            "json_rpc_url",
+            "stakes_file",
+            "stake_distribution",
+        ]))
         .get_matches();
+    let mut rng = rand::thread_rng();
+    // Stake-weighted sampling of peers, with nodes sorted by stake so that
+    // the origin is the node with the most stake.
+    let stakes = get_stakes(
+        &mut rng,
+        &matches,
+        matches.value_of_t_or_exit("cluster_size"),
+    );
     let config = {
         let gossip_push_fanout = matches.value_of_t_or_exit("gossip_push_fanout");
         Config {
@@ -119,11 +768,89 @@ fn main() {
                 .value_of_t("gossip_push_wide_fanout")
                 .unwrap_or(gossip_push_fanout),
             bounce_back: matches.value_of_t_or_exit("bounce_back"),
-            cluster_size: matches.value_of_t_or_exit("cluster_size"),
+            cluster_size: match &stakes {
+                None => matches.value_of_t_or_exit("cluster_size"),
+                Some(stakes) => stakes.len(),
+            },
             num_rounds: matches.value_of_t_or_exit("num_rounds"),
+            origin: match matches.value_of("origin").unwrap() {
+                "fixed" => OriginSelection::Fixed,
+                "random" => OriginSelection::Random,
+                "stake-weighted" => OriginSelection::StakeWeighted,
+                origin => panic!("invalid origin: {origin}"),
+            },
+            num_worst_nodes: matches.value_of_t_or_exit("num_worst_nodes"),
+            prune: matches.is_present("prune").then(|| PruneConfig {
+                rotate_active_set_rounds: matches.value_of_t_or_exit("rotate_active_set_rounds"),
+                min_ingress_nodes: matches.value_of_t_or_exit("min_ingress_nodes"),
+                stake_threshold: matches.value_of_t_or_exit("prune_stake_threshold"),
+                min_num_upserts: matches.value_of_t_or_exit("min_num_upserts"),
+                entry_capacity: matches.value_of_t_or_exit("received_cache_entry_capacity"),
+                num_dups_threshold: matches.value_of_t_or_exit("num_dups_threshold"),
+                report_interval: matches.value_of_t_or_exit("report_interval"),
+            }),
         }
     };
     info!("config: {:#?}", config);
-    let mut rng = rand::thread_rng();
-    run_fanout(&mut rng, &config);
+    if let (OriginSelection::StakeWeighted, Some(stakes)) = (config.origin, &stakes) {
+        if stakes.iter().all(|&stake| stake == 0) {
+            let message = "stake-weighted origin requires nonzero stakes\n";
+            clap::Error::raw(ErrorKind::InvalidValue, message).exit();
+        }
+    }
+    if let Some(prune_config) = &config.prune {
+        assert!(prune_config.rotate_active_set_rounds > 0);
+        assert!(prune_config.report_interval > 0);
+    }
+    if matches.is_present("analytic_only") {
+        print_fanout_stats(None, get_push_epidemic_stats(&config).as_ref());
+        return;
+    }
+    match &config.prune {
+        None => run_fanout(&mut rng, &config, stakes.as_deref()),
+        Some(prune_config) => {
+            run_fanout_with_prunes(&mut rng, &config, prune_config, stakes.as_deref())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn make_config(cluster_size: usize, gossip_push_fanout: f64, bounce_back: f64) -> Config {
+        Config {
+            gossip_push_fanout,
+            gossip_push_wide_fanout: gossip_push_fanout,
+            bounce_back,
+            cluster_size,
+            num_rounds: 0,
+            origin: OriginSelection::Fixed,
+            num_worst_nodes: 0,
+            prune: None,
+        }
+    }
+
+    #[test]
+    fn test_push_epidemic_stats() {
+        // Pushing to all other nodes reaches everyone in one hop, and all
+        // but the origin's packets are duplicates.
+        let stats = get_push_epidemic_stats(&make_config(10, 9.0, 0.0)).unwrap();
+        assert!((stats.propagation - 100.0).abs() < 1e-9);
+        assert!((stats.packets_node - 9.0).abs() < 1e-9);
+        assert!((stats.outdated - 90.0).abs() < 1e-9);
+        assert!((stats.waste - 9.0).abs() < 1e-9);
+        // For large clusters, the informed fraction x solves the final size
+        // equation x = 1 - e^{-f x}, which is 0.7968... for f = 2, and each
+        // informed node wastes f - 1 packets.
+        let stats = get_push_epidemic_stats(&make_config(1_000_000, 2.0, 0.0)).unwrap();
+        assert!((stats.propagation - 79.681_213).abs() < 1e-3);
+        assert!((stats.packets_node - 2.0 * 0.796_812_13).abs() < 1e-5);
+        assert!((stats.waste - 1.0).abs() < 1e-5);
+        // Bounced duplicates make the process supercritical once b * f >= 1.
+        assert!(get_push_epidemic_stats(&make_config(100, 2.0, 0.5)).is_none());
+        assert!(get_push_epidemic_stats(&make_config(1, 2.0, 0.0)).is_none());
+        let stats = get_push_epidemic_stats(&make_config(1_000_000, 2.0, 0.25)).unwrap();
+        assert!(stats.propagation > 79.7);
+    }
 }
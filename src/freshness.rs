@@ -0,0 +1,430 @@
+use {
+    crate::{
+        crds_value::CrdsValueType,
+        gossip::{CrdsEntry, CrdsKey, Node},
+        push_active_set::{get_stake_bucket, NUM_PUSH_ACTIVE_SET_ENTRIES},
+        NodeId,
+    },
+    solana_sdk::pubkey::Pubkey,
+    std::{
+        borrow::Borrow,
+        collections::{BTreeMap, HashMap},
+    },
+};
+
+pub const QUANTILES: [f64; 4] = [0.01, 0.1, 0.5, 0.9];
+
+/// Fraction of the most recent crds values (across all nodes) which a node
+/// holds in its crds table. Values which the node has purged count as misses
+/// even if no newer value has been generated since.
+#[derive(Clone, Debug)]
+pub struct NodeFreshness {
+    pub pubkey: Pubkey,
+    pub stake: u64,
+    pub num_hits: usize,
+    pub freshness: f64,
+    // Freshness where each crds value is weighted by its origin's stake.
+    pub stake_weighted_freshness: f64,
+}
+
+/// How up to date nodes are with crds values from an origin.
+#[derive(Clone, Debug)]
+pub struct OriginFreshness {
+    pub origin: Pubkey,
+    pub stake: u64,
+    // Fraction of (node, crds value) pairs where the node holds the most
+    // recent ordinal, excluding the origin itself.
+    pub freshness: f64,
+    // Number of (node, crds value) pairs which are behind.
+    pub num_stale: usize,
+    // Mean and max number of ordinals stale values are behind by, where a
+    // missing value lags by its most recent ordinal.
+    pub mean_lag: f64,
+    pub max_lag: u64,
+}
+
+/// How up to date nodes are with crds values of a type.
+#[derive(Clone, Debug)]
+pub struct TypeFreshness {
+    pub kind: CrdsValueType,
+    // Number of values of this type in the most recent crds table.
+    pub num_values: usize,
+    // Fraction of (node, crds value) pairs where the node holds the most
+    // recent ordinal, excluding values from the node itself.
+    pub freshness: f64,
+    // Freshness where each crds value is weighted by its origin's stake.
+    pub stake_weighted_freshness: f64,
+}
+
+/// Mean, min and quantiles of freshness across nodes.
+#[derive(Clone, Debug)]
+pub struct FreshnessSummary {
+    pub mean: f64,
+    pub min: f64,
+    // Values at each of QUANTILES.
+    pub quantiles: [f64; QUANTILES.len()],
+}
+
+/// Freshness broken down by stake bucket of the observing node and the
+/// origin of crds values.
+pub struct BucketFreshness(
+    [[(/*num hits:*/ usize, /*num values:*/ usize); NUM_PUSH_ACTIVE_SET_ENTRIES];
+        NUM_PUSH_ACTIVE_SET_ENTRIES],
+);
+
+pub fn get_node_freshness(
+    node: &Node,
+    table: &HashMap<CrdsKey, /*ordinal:*/ u64>, // Most recent crds table.
+    stakes: &[u64],                             // Indexed by NodeId.
+) -> NodeFreshness {
+    let node_table = node.table();
+    // Stake is summed once per crds value, which overflows u64.
+    let (mut num_hits, mut hits_stake, mut total_stake) = (0, 0u128, 0u128);
+    for (key, ordinal) in table {
+        let stake = u128::from(stakes[key.origin().index()]);
+        total_stake += stake;
+        if node_table.get(key).map(CrdsEntry::ordinal) == Some(*ordinal) {
+            num_hits += 1;
+            hits_stake += stake;
+        }
+    }
+    NodeFreshness {
+        pubkey: node.pubkey(),
+        stake: node.stake(),
+        num_hits,
+        freshness: num_hits as f64 / table.len().max(1) as f64,
+        stake_weighted_freshness: hits_stake as f64 / total_stake.max(1) as f64,
+    }
+}
+
+/// Returns freshness of crds values from each origin in the crds table,
+/// ordered from the worst served origin, i.e. by freshness and then by stake
+/// in descending order.
+pub fn get_origin_freshness<I, T>(
+    nodes: I,
+    table: &HashMap<CrdsKey, /*ordinal:*/ u64>, // Most recent crds table.
+    stakes: &[u64],                             // Indexed by NodeId.
+) -> Vec<OriginFreshness>
+where
+    I: IntoIterator<Item = T>,
+    T: Borrow<Node>,
+{
+    #[derive(Default)]
+    struct Counts {
+        num_values: usize,
+        num_stale: usize,
+        sum_lag: u64,
+        max_lag: u64,
+    }
+    let mut counts = HashMap::<NodeId, Counts>::new();
+    let mut pubkeys = HashMap::<NodeId, Pubkey>::new();
+    for node in nodes {
+        let node = node.borrow();
+        pubkeys.insert(node.id(), node.pubkey());
+        let node_table = node.table();
+        for (key, ordinal) in table {
+            if key.origin() == node.id() {
+                continue;
+            }
+            let counts = counts.entry(key.origin()).or_default();
+            counts.num_values += 1;
+            let lag = ordinal.saturating_sub(
+                node_table
+                    .get(key)
+                    .map(CrdsEntry::ordinal)
+                    .unwrap_or_default(),
+            );
+            if lag != 0 {
+                counts.num_stale += 1;
+                counts.sum_lag += lag;
+                counts.max_lag = counts.max_lag.max(lag);
+            }
+        }
+    }
+    let mut freshness: Vec<_> = counts
+        .into_iter()
+        .map(|(origin, counts)| OriginFreshness {
+            origin: pubkeys[&origin],
+            stake: stakes[origin.index()],
+            freshness: 1.0 - counts.num_stale as f64 / counts.num_values as f64,
+            num_stale: counts.num_stale,
+            mean_lag: counts.sum_lag as f64 / counts.num_stale.max(1) as f64,
+            max_lag: counts.max_lag,
+        })
+        .collect();
+    freshness.sort_unstable_by(|a, b| {
+        a.freshness
+            .total_cmp(&b.freshness)
+            .then_with(|| b.stake.cmp(&a.stake))
+    });
+    freshness
+}
+
+/// Returns freshness of crds values of each type in the crds table, ordered
+/// by type.
+pub fn get_type_freshness<I, T>(
+    nodes: I,
+    table: &HashMap<CrdsKey, /*ordinal:*/ u64>, // Most recent crds table.
+    stakes: &[u64],                             // Indexed by NodeId.
+) -> Vec<TypeFreshness>
+where
+    I: IntoIterator<Item = T>,
+    T: Borrow<Node>,
+{
+    #[derive(Default)]
+    struct Counts {
+        num_values: usize,
+        num_pairs: usize,
+        num_hits: usize,
+        // Summed over (node, crds value) pairs, which overflows u64.
+        total_stake: u128,
+        hits_stake: u128,
+    }
+    let mut counts = BTreeMap::<CrdsValueType, Counts>::new();
+    for key in table.keys() {
+        counts.entry(key.kind()).or_default().num_values += 1;
+    }
+    for node in nodes {
+        let node = node.borrow();
+        let node_table = node.table();
+        for (key, ordinal) in table {
+            if key.origin() == node.id() {
+                continue;
+            }
+            let stake = u128::from(stakes[key.origin().index()]);
+            let counts = counts.get_mut(&key.kind()).unwrap();
+            counts.num_pairs += 1;
+            counts.total_stake += stake;
+            if node_table.get(key).map(CrdsEntry::ordinal) == Some(*ordinal) {
+                counts.num_hits += 1;
+                counts.hits_stake += stake;
+            }
+        }
+    }
+    counts
+        .into_iter()
+        .map(|(kind, counts)| TypeFreshness {
+            kind,
+            num_values: counts.num_values,
+            freshness: counts.num_hits as f64 / counts.num_pairs.max(1) as f64,
+            stake_weighted_freshness: counts.hits_stake as f64 / counts.total_stake.max(1) as f64,
+        })
+        .collect()
+}
+
+impl FreshnessSummary {
+    /// Returns summary of the values each with the given weight, or None if
+    /// all weights are zero. Min is taken over values with non-zero weight.
+    pub fn new<I>(values: I) -> Option<Self>
+    where
+        I: IntoIterator<Item = (/*value:*/ f64, /*weight:*/ u64)>,
+    {
+        let mut values: Vec<_> = values
+            .into_iter()
+            .filter(|(_value, weight)| *weight != 0)
+            .collect();
+        values.sort_unstable_by(|(a, _), (b, _)| a.total_cmp(b));
+        let total_weight: u64 = values.iter().map(|(_value, weight)| weight).sum();
+        if total_weight == 0 {
+            return None;
+        }
+        let mean = values
+            .iter()
+            .map(|(value, weight)| value * *weight as f64)
+            .sum::<f64>()
+            / total_weight as f64;
+        let mut quantiles = [0.0; QUANTILES.len()];
+        for (quantile, q) in quantiles.iter_mut().zip(QUANTILES) {
+            // Smallest value where the cumulative weight reaches q.
+            let mut cumulative_weight = 0;
+            *quantile = values
+                .iter()
+                .find(|(_value, weight)| {
+                    cumulative_weight += weight;
+                    cumulative_weight as f64 >= q * total_weight as f64
+                })
+                .map(|(value, _weight)| *value)
+                .unwrap_or(values[values.len() - 1].0);
+        }
+        Some(Self {
+            mean,
+            min: values[0].0,
+            quantiles,
+        })
+    }
+}
+
+impl BucketFreshness {
+    pub fn new<I, T>(
+        nodes: I,
+        table: &HashMap<CrdsKey, /*ordinal:*/ u64>, // Most recent crds table.
+        stakes: &[u64],                             // Indexed by NodeId.
+    ) -> Self
+    where
+        I: IntoIterator<Item = T>,
+        T: Borrow<Node>,
+    {
+        let table: Vec<_> = table
+            .iter()
+            .map(|(key, ordinal)| {
+                let bucket = get_stake_bucket(Some(&stakes[key.origin().index()]));
+                (key, *ordinal, bucket)
+            })
+            .collect();
+        let mut out = [[(0, 0); NUM_PUSH_ACTIVE_SET_ENTRIES]; NUM_PUSH_ACTIVE_SET_ENTRIES];
+        for node in nodes {
+            let node = node.borrow();
+            let node_table = node.table();
+            let row = &mut out[get_stake_bucket(Some(&node.stake()))];
+            for (key, ordinal, bucket) in &table {
+                let entry = &mut row[*bucket];
+                entry.1 += 1;
+                if node_table.get(key).map(CrdsEntry::ordinal) == Some(*ordinal) {
+                    entry.0 += 1;
+                }
+            }
+        }
+        Self(out)
+    }
+
+    /// Returns freshness of values from origins in the given stake bucket
+    /// observed by nodes in the given stake bucket.
+    pub fn get(&self, observer: usize, origin: usize) -> Option<f64> {
+        let (num_hits, num_values) = self.0[observer][origin];
+        (num_values != 0).then(|| num_hits as f64 / num_values as f64)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        crate::{
+            gossip::{get_crds_table, make_gossip_nodes, timestamp, Config, Packet},
+            simulation::{ClusterSource, Simulation},
+        },
+        rand::SeedableRng,
+        rand_chacha::ChaChaRng,
+        std::sync::Arc,
+    };
+
+    #[test]
+    fn test_freshness_summary() {
+        assert!(FreshnessSummary::new([]).is_none());
+        assert!(FreshnessSummary::new([(0.5, 0)]).is_none());
+        let values = (0..100).map(|k| (k as f64 / 100.0, 1));
+        let summary = FreshnessSummary::new(values).unwrap();
+        assert!((summary.mean - 0.495).abs() < 1e-9);
+        assert_eq!(summary.min, 0.0);
+        assert_eq!(summary.quantiles, [0.0, 0.09, 0.49, 0.89]);
+        // Zero weights are ignored, and heavier values dominate.
+        let values = [(0.1, 0), (0.2, 1), (0.9, 3), (0.4, 1)];
+        let summary = FreshnessSummary::new(values).unwrap();
+        assert!((summary.mean - 0.66).abs() < 1e-9);
+        assert_eq!(summary.min, 0.2);
+        assert_eq!(summary.quantiles, [0.2, 0.2, 0.9, 0.9]);
+    }
+
+    #[test]
+    fn test_origin_freshness() {
+        let config = Config::new_for_tests();
+        let mut rng = ChaChaRng::seed_from_u64(42);
+        let stakes = [1, 5, 2];
+        let nodes = stakes.iter().map(|&stake| (Pubkey::new_unique(), stake));
+        let mut nodes = make_gossip_nodes(&mut rng, nodes, &config);
+        let key = |origin, index| CrdsKey::new(NodeId(origin), CrdsValueType::Vote, index);
+        let table = HashMap::from([
+            (key(0, 0), 3),
+            (key(1, 0), 2),
+            (key(1, 1), 5),
+            (key(2, 0), 1),
+        ]);
+        let now = timestamp();
+        let holdings = [
+            vec![(key(1, 0), 2), (key(1, 1), 4)],
+            vec![(key(0, 0), 3)],
+            vec![(key(0, 0), 1), (key(1, 1), 5)],
+        ];
+        for ((node, sender), holdings) in nodes.iter_mut().zip(holdings) {
+            for (key, ordinal) in holdings {
+                let packet = Packet::Push {
+                    from: NodeId(0),
+                    key,
+                    ordinal,
+                    wallclock: now,
+                    timestamp: now,
+                };
+                sender.send(Arc::new(packet)).unwrap();
+            }
+            node.consume_packets(&config, &());
+        }
+        let nodes: Vec<_> = nodes.into_iter().map(|(node, _sender)| node).collect();
+        let freshness = get_origin_freshness(&nodes, &table, &stakes);
+        // Origin 2 is held by no other node, and origins 0 and 1 tie on
+        // freshness, where origin 1 has more stake.
+        assert_eq!(
+            freshness.iter().map(|f| f.origin).collect::<Vec<_>>(),
+            [nodes[2].pubkey(), nodes[1].pubkey(), nodes[0].pubkey()]
+        );
+        assert_eq!(
+            freshness
+                .iter()
+                .map(|f| (f.freshness, f.num_stale))
+                .collect::<Vec<_>>(),
+            [(0.0, 2), (0.5, 2), (0.5, 1)]
+        );
+        assert_eq!(freshness[0].stake, 2);
+        // Missing values lag by their most recent ordinal.
+        assert_eq!(freshness[0].mean_lag, 1.0);
+        assert_eq!(freshness[0].max_lag, 1);
+        // Node 0 holds an outdated value and node 2 misses one.
+        assert_eq!(freshness[1].mean_lag, 1.5);
+        assert_eq!(freshness[1].max_lag, 2);
+        // Node 2 holds an outdated value, 2 ordinals behind.
+        assert_eq!(freshness[2].mean_lag, 2.0);
+        assert_eq!(freshness[2].max_lag, 2);
+        // Stake sums do not overflow even if each value carries max stake.
+        let freshness = get_node_freshness(&nodes[1], &table, &[u64::MAX; 3]);
+        assert_eq!(freshness.num_hits, 1);
+        assert_eq!(freshness.stake_weighted_freshness, 0.25);
+    }
+
+    #[test]
+    fn test_type_freshness() {
+        let config = Config {
+            crds_values: "vote:4:600:1,contact-info:1:250:0.5".parse().unwrap(),
+            ..Config::new_for_tests()
+        };
+        let stakes = (1..=10)
+            .map(|k| (Pubkey::new_unique(), k * 1_000_000_000))
+            .collect();
+        let mut simulation = Simulation::builder()
+            .cluster(ClusterSource::Stakes(stakes))
+            .config(config)
+            .seed(42)
+            .build()
+            .unwrap();
+        // Enough rounds for all values to be refreshed at least once.
+        for _ in 0..50 {
+            simulation.step().unwrap();
+        }
+        let stakes = simulation.stakes().to_vec();
+        let nodes = simulation.into_nodes().unwrap();
+        let table = get_crds_table(&nodes);
+        for key in table.keys() {
+            assert!(key.index() < config.crds_values.get(key.kind()).count);
+        }
+        let freshness = get_type_freshness(&nodes, &table, &stakes);
+        assert_eq!(
+            freshness
+                .iter()
+                .map(|f| (f.kind, f.num_values))
+                .collect::<Vec<_>>(),
+            [(CrdsValueType::ContactInfo, 10), (CrdsValueType::Vote, 40)]
+        );
+        for f in &freshness {
+            assert!(f.freshness > 0.5 && f.freshness <= 1.0, "{f:?}");
+            assert!(f.stake_weighted_freshness > 0.5, "{f:?}");
+        }
+    }
+}
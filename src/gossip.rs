@@ -1,9 +1,20 @@
 use {
-    crate::{push_active_set::PushActiveSet, received_cache::ReceivedCache, Error, Router},
+    crate::{
+        checkpoint::NodeCheckpoint,
+        crds_value::{CrdsValueModel, CrdsValueType},
+        graph::Graph,
+        push_active_set::{PushActiveSet, PUSH_ACTIVE_SET_FANOUT_MULTIPLE},
+        received_cache::{ReceivedCache, ReceivedCacheScorer},
+        simulation::Observer,
+        stakes::StakeViewModel,
+        Error, NodeId, Router,
+    },
     crossbeam_channel::{Receiver, Sender},
     itertools::Itertools,
     log::{error, info, trace},
-    rand::Rng,
+    rand::{Rng, SeedableRng},
+    rand_chacha::ChaChaRng,
+    serde::{Deserialize, Serialize},
     solana_client::{
         rpc_client::RpcClient, rpc_config::RpcGetVoteAccountsConfig,
         rpc_response::RpcVoteAccountStatus,
@@ -13,25 +24,62 @@ use {
         borrow::Borrow,
         cmp::{Ordering, Reverse},
         collections::{hash_map::Entry, HashMap, HashSet},
-        iter::{repeat, repeat_with},
+        iter::repeat,
+        ops::AddAssign,
         str::FromStr,
         sync::Arc,
-        time::{Duration, Instant},
+        time::{Duration, Instant, SystemTime, UNIX_EPOCH},
     },
 };
 
-pub(crate) const CRDS_UNIQUE_PUBKEY_CAPACITY: usize = 8192;
-const CRDS_GOSSIP_PRUNE_STAKE_THRESHOLD_PCT: f64 = 0.15;
+pub const CRDS_UNIQUE_PUBKEY_CAPACITY: usize = 8192;
 
 pub struct Node {
     clock: Instant,
     num_gossip_rounds: usize,
+    id: NodeId,
     pubkey: Pubkey,
     stake: u64,
+    // This node's view of stakes, indexed by NodeId.
+    stakes: Arc<[u64]>,
+    // View of stakes of the next epoch, adopted from the given gossip round.
+    next_stakes: Option<(/*round:*/ usize, Arc<[u64]>)>,
     table: HashMap<CrdsKey, CrdsEntry>,
+    // Nodes known from the start, from which the node discovers the rest of
+    // the cluster. Empty if nodes know the whole cluster.
+    entrypoints: Vec<NodeId>,
+    // Other nodes with crds values in the table.
+    origins: HashSet<NodeId>,
+    // Gossip round when the table first held values from all other nodes.
+    discovery_round: Option<usize>,
     active_set: PushActiveSet,
     received_cache: ReceivedCache,
     receiver: Receiver<Arc<Packet>>,
+    stats: NodeStats,
+    // Deliveries of the traced crds value to this node.
+    trace: Vec<TraceEdge>,
+    // Each node owns its rng so that its state can be checkpointed.
+    rng: ChaChaRng,
+    rng_seed: [u8; 32],
+}
+
+/// Cumulative counters of a gossip node.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct NodeStats {
+    // Bytes of crds values pushed to other nodes.
+    pub num_push_bytes_sent: u64,
+    pub num_prune_packets_sent: usize,
+    pub num_prune_packets_received: usize,
+    // Prune packets rejected because they are addressed to another node.
+    pub num_prunes_bad_destination: usize,
+    // Prune packets rejected because their wallclock is too old.
+    pub num_prunes_stale: usize,
+    // Prune packets rejected because the sender is not in the active set.
+    pub num_prunes_inactive: usize,
+    // Pushed crds values rejected because their wallclock has timed out.
+    pub num_push_expired: usize,
+    // Crds values purged from the table because they have timed out.
+    pub num_crds_purged: usize,
 }
 
 #[derive(Clone, Copy, Debug)]
@@ -42,41 +90,123 @@ pub struct Config {
     pub rotate_active_set_rounds: usize,
     // Min ingress number of nodes to keep when pruning received-cache.
     pub gossip_prune_min_ingress_nodes: usize,
+    // Maximum number of origins in each prune packet.
+    pub gossip_prune_max_origins: usize,
+    // Prune packets older than this are rejected by the receiver.
+    pub gossip_prune_timeout: Duration,
+    // Fraction of min stake of {this node, origin} which the aggregate
+    // ingress stake should exceed when pruning received-cache.
+    pub gossip_prune_stake_threshold_pct: f64,
+    // Number of origins tracked by the received-cache.
+    pub received_cache_capacity: usize,
+    // Minimum number of upserts before a received-cache entry can be pruned.
+    pub received_cache_min_num_upserts: usize,
+    // Max number of nodes tracked by each received-cache entry.
+    pub received_cache_entry_capacity: usize,
+    // Threshold for the number of duplicates before which a message
+    // is counted as timely towards node's score.
+    pub received_cache_num_dups_threshold: usize,
+    // How received-cache scores nodes for timeliness of delivered messages.
+    pub received_cache_scorer: ReceivedCacheScorer,
     // TODO: wide fanout
     // TODO: Maximum number of packets to push in each gossip round.
     pub gossip_push_capacity: usize,
     pub packet_drop_rate: f64,
-    pub num_crds: usize, // Number of crds values per node.
-    // Num of crds values generated by each node in each gossip round.
-    pub refresh_rate: f64,
+    // Number, size and refresh rate of crds values of each type per node.
+    pub crds_values: CrdsValueModel,
+    // How nodes learn about other nodes to push to.
+    pub discovery: Discovery,
+    // How each node's view of stakes deviates from the cluster stakes.
+    pub stake_view: StakeViewModel,
+    // Number of gossip rounds between purges of timed out crds values.
+    pub crds_purge_rounds: usize,
+    // Crds values older than these timeouts, by wallclock of the origin, are
+    // purged from the table, unless the origin's contact info is current.
+    pub crds_timeout_staked: Duration,
+    pub crds_timeout_unstaked: Duration,
     pub num_threads: usize,
     pub run_duration: Duration,
     // Number of gossip rounds before collecting stats.
     pub warm_up_rounds: usize,
+    // Crds value for which to record the propagation graph.
+    pub trace: Option<Trace>,
+}
+
+/// How nodes learn about other nodes in the cluster.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub enum Discovery {
+    /// Nodes know the whole cluster from the start.
+    Global,
+    /// Nodes start out knowing only the given number of entrypoints, shared
+    /// across the cluster, and discover other nodes from origins of crds
+    /// values in their table.
+    Entrypoints { num_entrypoints: usize },
+}
+
+/// Identifies a crds value whose dissemination is recorded.
+#[derive(Clone, Copy, Debug)]
+pub struct Trace {
+    pub key: CrdsKey,
+    pub ordinal: u64,
 }
 
-#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+/// Delivery of the traced crds value from one node to another.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct TraceEdge {
+    pub from: NodeId,
+    pub to: NodeId,
+    pub kind: TraceEdgeKind,
+    // Timestamp (us) when the value was pushed.
+    pub timestamp: u64,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub enum TraceEdgeKind {
+    // First delivery, which upserted the value.
+    First,
+    Duplicate,
+    // Delivered after the node already had a newer value.
+    Outdated,
+    // Delivered after the value timed out.
+    Expired,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize)]
 pub struct CrdsKey {
-    origin: Pubkey,
+    origin: NodeId,
+    kind: CrdsValueType,
     index: usize,
 }
 
-#[derive(Debug, Default)]
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
 pub struct CrdsEntry {
     ordinal: u64,
     num_dups: u8,
+    // Timestamp (us) of the first delivery of this ordinal.
+    timestamp: u64,
+    // Timestamp (us) when the origin generated this ordinal.
+    wallclock: u64,
 }
 
-#[derive(Clone)]
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
 pub enum Packet {
     Push {
-        from: Pubkey,
+        from: NodeId,
         key: CrdsKey,
         ordinal: u64,
+        // Timestamp (us) when the origin generated the value.
+        wallclock: u64,
+        // Timestamp (us) when the packet was pushed. Packets are routed
+        // without delay, so this doubles as the arrival time.
+        timestamp: u64,
     },
     Prune {
-        from: Pubkey,
-        origins: Vec<Pubkey>,
+        from: NodeId,
+        // Node which should apply the prune.
+        destination: NodeId,
+        origins: Vec<NodeId>,
+        // Timestamp (us) when the prune was generated.
+        wallclock: u64,
     },
 }
 
@@ -91,10 +221,10 @@ pub struct ConsumeOutput {
 
 enum UpsertError {
     Outdated,
-    Duplicate(/*num_dups:*/ u8),
+    Expired,
+    Duplicate(/*num_dups:*/ u8, /*delay:*/ Duration),
 }
 
-// TODO: should let nodes maintain their own view of the cluster?!
 // TODO: gossip loop 200ms delay!? listen vs gossip!?
 
 impl Node {
@@ -102,10 +232,19 @@ impl Node {
         self.stake
     }
 
+    pub fn id(&self) -> NodeId {
+        self.id
+    }
+
     pub fn pubkey(&self) -> Pubkey {
         self.pubkey
     }
 
+    /// This node's view of stakes, indexed by NodeId.
+    pub fn stakes(&self) -> &[u64] {
+        &self.stakes
+    }
+
     pub fn table(&self) -> &HashMap<CrdsKey, CrdsEntry> {
         &self.table
     }
@@ -114,18 +253,73 @@ impl Node {
         self.num_gossip_rounds
     }
 
-    pub fn run_gossip<R: Rng>(
+    pub fn entrypoints(&self) -> &[NodeId] {
+        &self.entrypoints
+    }
+
+    /// Returns the gossip round when the node first held crds values from
+    /// all other nodes, or None if it has not discovered the whole cluster.
+    pub fn discovery_round(&self) -> Option<usize> {
+        self.discovery_round
+    }
+
+    pub fn stats(&self) -> &NodeStats {
+        &self.stats
+    }
+
+    pub fn trace(&self) -> &[TraceEdge] {
+        &self.trace
+    }
+
+    /// Returns nodes which this node pushes crds values from the origin to,
+    /// i.e. the active-set entry minus nodes which have pruned the origin,
+    /// truncated to the push fanout (rounded up).
+    pub fn get_push_peers(&self, origin: NodeId, config: &Config) -> Vec<NodeId> {
+        let gossip_push_fanout = if origin == self.id {
+            config.gossip_push_wide_fanout
+        } else {
+            config.gossip_push_fanout
+        };
+        self.active_set
+            .get_nodes(self.id, origin, |_| false, &self.stakes)
+            .take(gossip_push_fanout.ceil() as usize)
+            .collect()
+    }
+
+    pub fn run_gossip(
+        &mut self,
+        config: &Config,
+        router: &Router<Arc<Packet>>,
+        observer: &dyn Observer,
+    ) -> Result<(), Error> {
+        // Methods below borrow self mutably, so the round runs on a copy of
+        // the node's rng which is then written back.
+        let mut rng = self.rng.clone();
+        let result = self.run_gossip_round(&mut rng, config, router, observer);
+        self.rng = rng;
+        result
+    }
+
+    fn run_gossip_round<R: Rng>(
         &mut self,
         rng: &mut R,
         config: &Config,
-        stakes: &HashMap<Pubkey, u64>,
         router: &Router<Arc<Packet>>,
+        observer: &dyn Observer,
     ) -> Result<(), Error> {
         let elapsed = self.clock.elapsed();
         self.clock = Instant::now();
         self.num_gossip_rounds += 1;
+        if matches!(&self.next_stakes, Some((round, _)) if *round <= self.num_gossip_rounds) {
+            let (_round, stakes) = self.next_stakes.take().unwrap();
+            self.stakes = stakes;
+        }
         if self.num_gossip_rounds % config.rotate_active_set_rounds == 1 {
-            self.rotate_active_set(rng, config.gossip_push_fanout as usize, stakes);
+            self.rotate_active_set(rng, config);
+            observer.on_rotation(self.id);
+        }
+        if self.num_gossip_rounds % config.crds_purge_rounds == 0 {
+            self.purge(config, timestamp());
         }
         // Drain the channel for incomming packets.
         // Insert new messages into the CRDS table.
@@ -135,46 +329,57 @@ impl Node {
             num_prunes,
             num_outdated,
             num_duplicates,
-        } = self.consume_packets(stakes);
-        // Send prune messages for upserted origins.
+        } = self.consume_packets(config, observer);
+        if self.discovery_round.is_none() && self.origins.len() + 1 == self.stakes.len() {
+            self.discovery_round = Some(self.num_gossip_rounds);
+        }
+        // Send prune messages for upserted origins, in a fixed order so that
+        // seeded runs are reproducible.
         {
-            let origins = keys.iter().map(|key| key.origin);
-            self.send_prunes(rng, origins, config, stakes, router)?;
+            let origins = keys.iter().map(|key| key.origin).sorted_unstable().dedup();
+            self.send_prunes(rng, origins, config, router, observer)?;
         }
         // Refresh own gossip entries!
-        keys.extend(self.refresh_entries(rng, config));
-        // Sort updated keys by origin's stake.
+        keys.extend(self.refresh_entries(rng, config, observer));
+        // Sort updated keys by origin's stake, breaking ties by key so that
+        // seeded runs are reproducible.
         let keys: Vec<_> = keys
             .into_iter()
             .map(|key| {
-                let stake = stakes.get(&key.origin).copied().unwrap_or_default();
+                let stake = self.stakes[key.origin.index()];
                 (stake, key)
             })
-            .sorted_unstable_by_key(|(stake, _)| Reverse(*stake))
+            .sorted_unstable_by_key(|&(stake, key)| (Reverse(stake), key))
             .map(|(_stake, key)| key)
             .collect();
         let num_keys = keys.len();
         // Push/fanout overwritten keys to other nodes.
         for key in keys {
+            let entry = &self.table[&key];
             let packet = Arc::new(Packet::Push {
-                from: self.pubkey,
+                from: self.id,
                 key,
-                ordinal: self.table[&key].ordinal,
+                ordinal: entry.ordinal,
+                wallclock: entry.wallclock,
+                timestamp: timestamp(),
             });
-            let gossip_push_fanout = if key.origin == self.pubkey {
+            let gossip_push_fanout = if key.origin == self.id {
                 config.gossip_push_wide_fanout
             } else {
                 config.gossip_push_fanout
             };
             let gossip_push_fanout =
                 gossip_push_fanout as usize + rng.gen_bool(gossip_push_fanout % 1.0) as usize;
+            let size = config.crds_values.get(key.kind).size as u64;
             for node in self
                 .active_set
-                .get_nodes(&self.pubkey, &key.origin, |_| false, stakes)
+                .get_nodes(self.id, key.origin, |_| false, &self.stakes)
                 .take(gossip_push_fanout)
             {
-                assert_ne!(node, &self.pubkey);
+                assert_ne!(node, self.id);
+                observer.on_packet_sent(self.id, node, &packet);
                 router.send(rng, node, packet.clone())?;
+                self.stats.num_push_bytes_sent += size;
             }
         }
         let get_ratio = |num| {
@@ -208,100 +413,186 @@ impl Node {
     fn send_prunes<R: Rng>(
         &mut self,
         rng: &mut R,
-        origins: impl IntoIterator<Item = Pubkey>, // upserted origins
+        origins: impl IntoIterator<Item = NodeId>, // upserted origins
         config: &Config,
-        stakes: &HashMap<Pubkey, u64>,
         router: &Router<Arc<Packet>>,
+        observer: &dyn Observer,
     ) -> Result<(), Error> {
         let prunes = origins
             .into_iter()
-            .flat_map(|origin| {
-                self.received_cache
-                    .prune(
-                        &self.pubkey,
-                        origin,
-                        CRDS_GOSSIP_PRUNE_STAKE_THRESHOLD_PCT,
-                        config.gossip_prune_min_ingress_nodes,
-                        stakes,
-                    )
-                    .zip(repeat(origin))
+            .filter_map(|origin| {
+                self.received_cache.prune(
+                    self.id,
+                    origin,
+                    config.gossip_prune_stake_threshold_pct,
+                    config.gossip_prune_min_ingress_nodes,
+                    &self.stakes,
+                )
+            })
+            .inspect(|decision| observer.on_prune(decision))
+            .flat_map(|decision| {
+                let origin = decision.origin;
+                decision.pruned().zip(repeat(origin))
             })
             .into_group_map();
-        for (node, origins) in prunes {
-            let packet = Packet::Prune {
-                from: self.pubkey,
-                origins,
-            };
-            router.send(rng, &node, Arc::new(packet))?;
+        let wallclock = timestamp();
+        for (node, origins) in prunes
+            .into_iter()
+            .sorted_unstable_by_key(|(node, _origins)| *node)
+        {
+            for origins in origins.chunks(config.gossip_prune_max_origins) {
+                let packet = Packet::Prune {
+                    from: self.id,
+                    destination: node,
+                    origins: origins.to_vec(),
+                    wallclock,
+                };
+                observer.on_packet_sent(self.id, node, &packet);
+                router.send(rng, node, Arc::new(packet))?;
+                self.stats.num_prune_packets_sent += 1;
+            }
         }
         Ok(())
     }
 
     // Refreshes own gossip entries, returning upserted crds keys.
-    fn refresh_entries<'a, R: Rng>(
-        &'a mut self,
-        rng: &'a mut R,
-        config: &'a Config,
-    ) -> impl Iterator<Item = CrdsKey> + 'a {
-        let num_refresh =
-            config.refresh_rate as usize + rng.gen_bool(config.refresh_rate % 1.0) as usize;
-        repeat_with(|| rng.gen_range(0, config.num_crds))
-            .take(num_refresh)
-            .map(|index| {
+    fn refresh_entries<R: Rng>(
+        &mut self,
+        rng: &mut R,
+        config: &Config,
+        observer: &dyn Observer,
+    ) -> Vec<CrdsKey> {
+        let mut keys = Vec::new();
+        for (kind, params) in config.crds_values.iter() {
+            let num_refresh =
+                params.refresh_rate as usize + rng.gen_bool(params.refresh_rate % 1.0) as usize;
+            for _ in 0..num_refresh {
                 let key = CrdsKey {
-                    origin: self.pubkey,
-                    index,
+                    origin: self.id,
+                    kind,
+                    index: rng.gen_range(0, params.count),
                 };
-                self.table.entry(key).or_default().ordinal += 1;
-                key
-            })
+                let entry = self.table.entry(key).or_default();
+                entry.ordinal += 1;
+                entry.timestamp = timestamp();
+                entry.wallclock = entry.timestamp;
+                observer.on_upsert(self.id, key, entry.ordinal);
+                keys.push(key);
+            }
+        }
+        keys
     }
 
     /// Drains the channel for incoming packets and updates crds table.
-    pub fn consume_packets(&mut self, stakes: &HashMap<Pubkey, u64>) -> ConsumeOutput {
+    pub fn consume_packets(&mut self, config: &Config, observer: &dyn Observer) -> ConsumeOutput {
         let packets: Vec<_> = self.receiver.try_iter().collect();
+        let now = timestamp();
         // Insert new messages into the CRDS table.
         let mut out = ConsumeOutput {
             num_packets: packets.len(),
             ..ConsumeOutput::default()
         };
         for packet in packets {
+            observer.on_packet_received(self.id, &packet);
             match *packet {
-                Packet::Push { from, key, ordinal } => {
-                    match self.upsert(key, ordinal) {
+                Packet::Push {
+                    from,
+                    key,
+                    ordinal,
+                    wallclock,
+                    timestamp,
+                } => {
+                    let age = Duration::from_micros(now.saturating_sub(wallclock));
+                    let result = if age > get_crds_timeout(config, key.origin, &self.stakes) {
+                        Err(UpsertError::Expired)
+                    } else {
+                        self.upsert(key, ordinal, wallclock, timestamp)
+                    };
+                    if matches!(config.trace, Some(trace)
+                        if trace.key == key && trace.ordinal == ordinal)
+                    {
+                        self.record_trace(from, &result, timestamp);
+                    }
+                    match result {
                         Ok(()) => {
-                            self.received_cache
-                                .record(key.origin, from, /*num_dups:*/ 0);
+                            observer.on_upsert(self.id, key, ordinal);
+                            self.received_cache.record(
+                                key.origin,
+                                from,
+                                0,              // num_dups
+                                Duration::ZERO, // delay
+                            );
                             out.keys.insert(key);
                         }
                         Err(UpsertError::Outdated) => {
                             self.received_cache.record(
                                 key.origin,
                                 from,
-                                usize::MAX, // num_dups
+                                usize::MAX,    // num_dups
+                                Duration::MAX, // delay
                             );
                             out.num_outdated += 1;
                         }
-                        Err(UpsertError::Duplicate(num_dups)) => {
-                            self.received_cache
-                                .record(key.origin, from, usize::from(num_dups));
+                        Err(UpsertError::Expired) => {
+                            self.stats.num_push_expired += 1;
+                        }
+                        Err(UpsertError::Duplicate(num_dups, delay)) => {
+                            self.received_cache.record(
+                                key.origin,
+                                from,
+                                usize::from(num_dups),
+                                delay,
+                            );
                             out.num_duplicates += 1;
                         }
                     }
                 }
                 Packet::Prune {
-                    ref from,
+                    from,
+                    destination,
                     ref origins,
+                    wallclock,
                 } => {
                     out.num_prunes += 1;
-                    self.active_set.prune(&self.pubkey, from, origins, stakes);
+                    self.stats.num_prune_packets_received += 1;
+                    let age = Duration::from_micros(now.saturating_sub(wallclock));
+                    if destination != self.id {
+                        self.stats.num_prunes_bad_destination += 1;
+                    } else if age > config.gossip_prune_timeout {
+                        self.stats.num_prunes_stale += 1;
+                    } else if !self.active_set.contains(from) {
+                        self.stats.num_prunes_inactive += 1;
+                    } else {
+                        self.active_set.prune(self.id, from, origins, &self.stakes);
+                    }
                 }
             }
         }
         out
     }
 
-    fn upsert(&mut self, key: CrdsKey, ordinal: u64) -> Result<(), UpsertError> {
+    fn record_trace(&mut self, from: NodeId, result: &Result<(), UpsertError>, timestamp: u64) {
+        let kind = match result {
+            Ok(()) => TraceEdgeKind::First,
+            Err(UpsertError::Duplicate(..)) => TraceEdgeKind::Duplicate,
+            Err(UpsertError::Outdated) => TraceEdgeKind::Outdated,
+            Err(UpsertError::Expired) => TraceEdgeKind::Expired,
+        };
+        self.trace.push(TraceEdge {
+            from,
+            to: self.id,
+            kind,
+            timestamp,
+        });
+    }
+
+    fn upsert(
+        &mut self,
+        key: CrdsKey,
+        ordinal: u64,
+        wallclock: u64,
+        timestamp: u64,
+    ) -> Result<(), UpsertError> {
         match self.table.entry(key) {
             Entry::Occupied(mut entry) => {
                 let entry = entry.get_mut();
@@ -310,12 +601,18 @@ impl Node {
                         *entry = CrdsEntry {
                             ordinal,
                             num_dups: 0u8,
+                            timestamp,
+                            wallclock,
                         };
                         Ok(())
                     }
                     Ordering::Equal => {
                         entry.num_dups = entry.num_dups.saturating_add(1u8);
-                        Err(UpsertError::Duplicate(entry.num_dups))
+                        let delay = timestamp.saturating_sub(entry.timestamp);
+                        Err(UpsertError::Duplicate(
+                            entry.num_dups,
+                            Duration::from_micros(delay),
+                        ))
                     }
                     Ordering::Greater => Err(UpsertError::Outdated),
                 }
@@ -324,31 +621,272 @@ impl Node {
                 entry.insert(CrdsEntry {
                     ordinal,
                     num_dups: 0u8,
+                    timestamp,
+                    wallclock,
                 });
+                if key.origin != self.id {
+                    self.origins.insert(key.origin);
+                }
                 Ok(())
             }
         }
     }
 
-    fn rotate_active_set<R: Rng>(
-        &mut self,
-        rng: &mut R,
-        gossip_push_fanout: usize,
-        stakes: &HashMap<Pubkey, u64>,
-    ) {
-        // Gossip nodes to be sampled for each push active set.
-        // TODO: this should only be a set of entrypoints not all staked nodes.
-        let nodes: Vec<_> = stakes
-            .keys()
-            .copied()
-            .chain(self.table.keys().map(|key| key.origin))
-            .filter(|pubkey| pubkey != &self.pubkey)
-            .collect::<HashSet<_>>()
-            .into_iter()
+    /// Updates the node's stake and, after a random lag, its view of stakes
+    /// to the stakes of a new epoch, indexed by NodeId.
+    pub fn set_epoch_stakes(&mut self, config: &Config, stakes: &Arc<[u64]>) {
+        self.stake = stakes[self.id.index()];
+        let view = config.stake_view.make_view(&mut self.rng, self.id, stakes);
+        let lag = match config.stake_view.max_lag_rounds {
+            0 => 0,
+            max_lag_rounds => self.rng.gen_range(0, max_lag_rounds + 1),
+        };
+        if lag == 0 {
+            self.stakes = view;
+            self.next_stakes = None;
+        } else {
+            self.next_stakes = Some((self.num_gossip_rounds + lag, view));
+        }
+    }
+
+    /// Purges crds values which have timed out as of the given timestamp
+    /// (us). As in the validator, values from an origin are retained while
+    /// its contact info is current, and the node never purges its own values.
+    pub fn purge(&mut self, config: &Config, now: u64) {
+        let stakes = &self.stakes;
+        let is_expired = |key: &CrdsKey, entry: &CrdsEntry| {
+            let timeout = get_crds_timeout(config, key.origin, stakes);
+            Duration::from_micros(now.saturating_sub(entry.wallclock)) > timeout
+        };
+        let current_origins: HashSet<NodeId> = self
+            .table
+            .iter()
+            .filter(|(key, entry)| {
+                key.kind == CrdsValueType::ContactInfo && !is_expired(key, entry)
+            })
+            .map(|(key, _entry)| key.origin)
             .collect();
+        let num_entries = self.table.len();
+        let id = self.id;
+        self.table.retain(|key, entry| {
+            key.origin == id || current_origins.contains(&key.origin) || !is_expired(key, entry)
+        });
+        if self.table.len() != num_entries {
+            self.stats.num_crds_purged += num_entries - self.table.len();
+            self.origins = get_origins(id, &self.table);
+        }
+    }
+
+    fn rotate_active_set<R: Rng>(&mut self, rng: &mut R, config: &Config) {
+        // Gossip nodes to be sampled for each push active set, sorted so
+        // that runs are reproducible.
+        let nodes: Vec<_> = match config.discovery {
+            Discovery::Global => (0..self.stakes.len())
+                .map(NodeId::from)
+                .filter(|&node| node != self.id)
+                .collect(),
+            Discovery::Entrypoints { .. } => self
+                .entrypoints
+                .iter()
+                .chain(&self.origins)
+                .copied()
+                .filter(|&node| node != self.id)
+                .sorted_unstable()
+                .dedup()
+                .collect(),
+        };
         let cluster_size = nodes.len();
+        let size = config.gossip_push_fanout as usize * PUSH_ACTIVE_SET_FANOUT_MULTIPLE;
         self.active_set
-            .rotate(rng, gossip_push_fanout * 3, cluster_size, &nodes, stakes);
+            .rotate(rng, size, cluster_size, &nodes, &self.stakes);
+    }
+
+    // Returns the node's state along with packets in flight to the node.
+    // Packets are drained from the node's channel and sent back through the
+    // router, so that the node can keep running after the checkpoint.
+    pub(crate) fn checkpoint(
+        &mut self,
+        router: &Router<Arc<Packet>>,
+    ) -> Result<NodeCheckpoint, Error> {
+        let packets: Vec<Arc<Packet>> = self.receiver.try_iter().collect();
+        for packet in &packets {
+            router.send_reliable(self.id, packet.clone())?;
+        }
+        Ok(NodeCheckpoint {
+            num_gossip_rounds: self.num_gossip_rounds,
+            id: self.id,
+            pubkey: self.pubkey,
+            stake: self.stake,
+            stakes: self.stakes.to_vec(),
+            next_stakes: self
+                .next_stakes
+                .as_ref()
+                .map(|(round, stakes)| (*round, stakes.to_vec())),
+            table: self.table.clone(),
+            entrypoints: self.entrypoints.clone(),
+            discovery_round: self.discovery_round,
+            active_set: self.active_set.checkpoint(),
+            received_cache: self.received_cache.clone(),
+            stats: self.stats.clone(),
+            trace: self.trace.clone(),
+            rng_seed: self.rng_seed,
+            rng_word_pos: self.rng.get_word_pos(),
+            packets: packets.iter().map(|packet| Packet::clone(packet)).collect(),
+        })
+    }
+
+    // Replaces the node's rng, so that runs forked from the same checkpoint
+    // diverge.
+    pub(crate) fn reseed(&mut self, rng_seed: [u8; 32]) {
+        self.rng = ChaChaRng::from_seed(rng_seed);
+        self.rng_seed = rng_seed;
+    }
+
+    // Restores the node from the checkpoint, shifting all timestamps forward
+    // by the given offset (us). Packets in flight are queued in the returned
+    // channel. Views of stakes equal to the given cluster stakes share them.
+    pub(crate) fn from_checkpoint(
+        checkpoint: NodeCheckpoint,
+        offset: u64,
+        cluster_stakes: &Arc<[u64]>,
+    ) -> (Self, Sender<Arc<Packet>>) {
+        let NodeCheckpoint {
+            num_gossip_rounds,
+            id,
+            pubkey,
+            stake,
+            stakes,
+            next_stakes,
+            mut table,
+            entrypoints,
+            discovery_round,
+            active_set,
+            received_cache,
+            stats,
+            mut trace,
+            rng_seed,
+            rng_word_pos,
+            packets,
+        } = checkpoint;
+        for entry in table.values_mut() {
+            entry.timestamp += offset;
+            entry.wallclock += offset;
+        }
+        for edge in &mut trace {
+            edge.timestamp += offset;
+        }
+        let (sender, receiver) = crossbeam_channel::unbounded();
+        for mut packet in packets {
+            match &mut packet {
+                Packet::Push {
+                    wallclock,
+                    timestamp,
+                    ..
+                } => {
+                    *wallclock += offset;
+                    *timestamp += offset;
+                }
+                Packet::Prune { wallclock, .. } => *wallclock += offset,
+            }
+            sender.send(Arc::new(packet)).unwrap();
+        }
+        let share_stakes = |stakes: Vec<u64>| -> Arc<[u64]> {
+            if stakes[..] == cluster_stakes[..] {
+                cluster_stakes.clone()
+            } else {
+                Arc::from(stakes)
+            }
+        };
+        let mut rng = ChaChaRng::from_seed(rng_seed);
+        rng.set_word_pos(rng_word_pos);
+        let node = Node {
+            clock: Instant::now(),
+            num_gossip_rounds,
+            id,
+            pubkey,
+            stake,
+            stakes: share_stakes(stakes),
+            next_stakes: next_stakes.map(|(round, stakes)| (round, share_stakes(stakes))),
+            origins: get_origins(id, &table),
+            table,
+            entrypoints,
+            discovery_round,
+            active_set: PushActiveSet::from_checkpoint(active_set),
+            received_cache,
+            receiver,
+            stats,
+            trace,
+            rng,
+            rng_seed,
+        };
+        (node, sender)
+    }
+}
+
+impl Config {
+    #[cfg(test)]
+    pub(crate) fn new_for_tests() -> Self {
+        Self {
+            gossip_push_fanout: 3.0,
+            gossip_push_wide_fanout: 3.0,
+            rotate_active_set_rounds: 4,
+            gossip_prune_min_ingress_nodes: 2,
+            gossip_prune_max_origins: 32,
+            gossip_prune_timeout: Duration::from_secs(60),
+            gossip_prune_stake_threshold_pct: 0.15,
+            received_cache_capacity: 64,
+            received_cache_min_num_upserts: 4,
+            received_cache_entry_capacity: 16,
+            received_cache_num_dups_threshold: 2,
+            received_cache_scorer: ReceivedCacheScorer::NumDups,
+            gossip_push_capacity: 1024,
+            packet_drop_rate: 0.0,
+            crds_values: "vote:4:600:1".parse().unwrap(),
+            discovery: Discovery::Global,
+            stake_view: StakeViewModel::EXACT,
+            crds_purge_rounds: 4,
+            crds_timeout_staked: Duration::from_secs(60),
+            crds_timeout_unstaked: Duration::from_secs(60),
+            num_threads: 1,
+            run_duration: Duration::ZERO,
+            warm_up_rounds: 0,
+            trace: None,
+        }
+    }
+}
+
+impl AddAssign<&NodeStats> for NodeStats {
+    fn add_assign(&mut self, other: &NodeStats) {
+        self.num_push_bytes_sent += other.num_push_bytes_sent;
+        self.num_prune_packets_sent += other.num_prune_packets_sent;
+        self.num_prune_packets_received += other.num_prune_packets_received;
+        self.num_prunes_bad_destination += other.num_prunes_bad_destination;
+        self.num_prunes_stale += other.num_prunes_stale;
+        self.num_prunes_inactive += other.num_prunes_inactive;
+        self.num_push_expired += other.num_push_expired;
+        self.num_crds_purged += other.num_crds_purged;
+    }
+}
+
+impl CrdsKey {
+    pub fn new(origin: NodeId, kind: CrdsValueType, index: usize) -> Self {
+        Self {
+            origin,
+            kind,
+            index,
+        }
+    }
+
+    pub fn origin(&self) -> NodeId {
+        self.origin
+    }
+
+    pub fn kind(&self) -> CrdsValueType {
+        self.kind
+    }
+
+    pub fn index(&self) -> usize {
+        self.index
     }
 }
 
@@ -356,12 +894,33 @@ impl CrdsEntry {
     pub fn ordinal(&self) -> u64 {
         self.ordinal
     }
+
+    pub fn wallclock(&self) -> u64 {
+        self.wallclock
+    }
 }
 
-#[allow(clippy::type_complexity)]
-pub fn make_gossip_cluster(
-    rpc_client: &RpcClient,
-) -> Result<Vec<(Node, Sender<Arc<Packet>>)>, Error> {
+// Returns other nodes with crds values in the table.
+fn get_origins(node: NodeId, table: &HashMap<CrdsKey, CrdsEntry>) -> HashSet<NodeId> {
+    table
+        .keys()
+        .map(|key| key.origin)
+        .filter(|&origin| origin != node)
+        .collect()
+}
+
+// Returns how long crds values from the origin are retained, which is
+// longer for staked origins.
+fn get_crds_timeout(config: &Config, origin: NodeId, stakes: &[u64]) -> Duration {
+    if stakes[origin.index()] == 0 {
+        config.crds_timeout_unstaked
+    } else {
+        config.crds_timeout_staked
+    }
+}
+
+/// Returns node pubkeys in the cluster along with their activated stake.
+pub fn get_cluster_stakes(rpc_client: &RpcClient) -> Result<Vec<(Pubkey, /*stake:*/ u64)>, Error> {
     let config = RpcGetVoteAccountsConfig {
         vote_pubkey: None,
         commitment: Some(CommitmentConfig::finalized()),
@@ -389,37 +948,184 @@ pub fn make_gossip_cluster(
     } else {
         info!("shred versions: {:?}", shred_versions);
     }
-    let now = Instant::now();
     let nodes: Vec<_> = nodes
         .into_iter()
         .map(|node| {
             let stake = stakes.get(&node.pubkey).copied().unwrap_or_default();
             let pubkey = Pubkey::from_str(&node.pubkey)?;
+            Ok((pubkey, stake))
+        })
+        .collect::<Result<_, Error>>()?;
+    let num_nodes_staked = nodes.iter().filter(|(_pubkey, stake)| *stake != 0).count();
+    info!("num of staked nodes in cluster: {}", num_nodes_staked);
+    info!("num of cluster nodes: {}", nodes.len());
+    let active_stake: u64 = stakes.values().sum();
+    let cluster_stake: u64 = nodes.iter().map(|(_pubkey, stake)| stake).sum();
+    info!("active stake:  {}", active_stake);
+    info!("cluster stake: {}", cluster_stake);
+    Ok(nodes)
+}
+
+/// Returns gossip nodes with the given pubkeys and stakes, where each node's
+/// NodeId is its index in the input. Each node's rng is seeded from `rng`,
+/// which also samples the entrypoints if nodes discover the cluster from
+/// entrypoints. Each node's view of stakes is sampled from its own rng.
+pub fn make_gossip_nodes<R, I>(
+    rng: &mut R,
+    stakes: I,
+    config: &Config,
+) -> Vec<(Node, Sender<Arc<Packet>>)>
+where
+    R: Rng,
+    I: IntoIterator<Item = (Pubkey, /*stake:*/ u64)>,
+{
+    let now = Instant::now();
+    let stakes: Vec<_> = stakes.into_iter().collect();
+    let cluster_stakes: Arc<[u64]> = stakes.iter().map(|&(_pubkey, stake)| stake).collect();
+    let entrypoints: Vec<NodeId> = match config.discovery {
+        Discovery::Global => Vec::default(),
+        Discovery::Entrypoints { num_entrypoints } => {
+            let num_entrypoints = num_entrypoints.min(stakes.len());
+            rand::seq::index::sample(rng, stakes.len(), num_entrypoints)
+                .into_iter()
+                .map(NodeId::from)
+                .collect()
+        }
+    };
+    stakes
+        .into_iter()
+        .enumerate()
+        .map(|(k, (pubkey, stake))| {
             let (sender, receiver) = crossbeam_channel::unbounded();
+            let rng_seed = rng.gen();
+            let id = NodeId::from(k);
+            let mut rng = ChaChaRng::from_seed(rng_seed);
             let node = Node {
                 clock: now,
                 num_gossip_rounds: 0,
+                id,
                 stake,
+                stakes: config.stake_view.make_view(&mut rng, id, &cluster_stakes),
+                next_stakes: None,
                 pubkey,
                 table: HashMap::default(),
+                entrypoints: entrypoints.clone(),
+                origins: HashSet::default(),
+                discovery_round: None,
                 active_set: PushActiveSet::default(),
-                received_cache: ReceivedCache::new(2 * CRDS_UNIQUE_PUBKEY_CAPACITY),
+                received_cache: ReceivedCache::new(
+                    config.received_cache_capacity,
+                    config.received_cache_min_num_upserts,
+                    config.received_cache_entry_capacity,
+                    config.received_cache_num_dups_threshold,
+                    config.received_cache_scorer,
+                ),
                 receiver,
+                stats: NodeStats::default(),
+                trace: Vec::default(),
+                rng,
+                rng_seed,
             };
-            Ok((node, sender))
+            (node, sender)
         })
-        .collect::<Result<_, Error>>()?;
-    let num_nodes_staked = nodes
+        .collect()
+}
+
+/// Returns current wallclock in microseconds.
+pub fn timestamp() -> u64 {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+    u64::try_from(now.as_micros()).unwrap()
+}
+
+/// Returns the propagation graph of the traced crds value, where edges are
+/// labeled by the kind of delivery, the delay (us) since the value was
+/// first pushed and, for first deliveries, the hop count from the origin.
+pub fn get_propagation_graph<I, T>(nodes: I, trace: &Trace) -> Graph
+where
+    I: IntoIterator<Item = T>,
+    T: Borrow<Node>,
+{
+    let mut graph = Graph::default();
+    let mut pubkeys = HashMap::<NodeId, Pubkey>::new();
+    let mut edges = Vec::<TraceEdge>::new();
+    for node in nodes {
+        let node = node.borrow();
+        graph.add_node(node.pubkey, node.stake);
+        pubkeys.insert(node.id, node.pubkey);
+        edges.extend_from_slice(node.trace());
+    }
+    edges.sort_unstable_by_key(|edge| edge.timestamp);
+    let start = edges.first().map(|edge| edge.timestamp).unwrap_or_default();
+    // First deliveries form a tree rooted at the origin, so the hop count
+    // of a node is one more than that of its parent.
+    let mut hops = HashMap::<NodeId, usize>::from([(trace.key.origin, 0)]);
+    let parents: HashMap<NodeId, NodeId> = edges
         .iter()
-        .filter(|(node, _sender)| node.stake != 0)
-        .count();
-    info!("num of staked nodes in cluster: {}", num_nodes_staked);
-    info!("num of cluster nodes: {}", nodes.len());
-    let active_stake: u64 = stakes.values().sum();
-    let cluster_stake: u64 = nodes.iter().map(|(node, _sender)| node.stake).sum();
-    info!("active stake:  {}", active_stake);
-    info!("cluster stake: {}", cluster_stake);
-    Ok(nodes)
+        .filter(|edge| edge.kind == TraceEdgeKind::First)
+        .map(|edge| (edge.to, edge.from))
+        .collect();
+    fn get_hops(
+        node: NodeId,
+        parents: &HashMap<NodeId, NodeId>,
+        hops: &mut HashMap<NodeId, usize>,
+    ) -> Option<usize> {
+        if let Some(&num_hops) = hops.get(&node) {
+            return Some(num_hops);
+        }
+        let num_hops = get_hops(*parents.get(&node)?, parents, hops)? + 1;
+        hops.insert(node, num_hops);
+        Some(num_hops)
+    }
+    for edge in edges {
+        let mut attrs = vec![
+            ("kind", format!("{:?}", edge.kind).to_lowercase()),
+            ("delay_us", (edge.timestamp - start).to_string()),
+        ];
+        if edge.kind == TraceEdgeKind::First {
+            if let Some(num_hops) = get_hops(edge.to, &parents, &mut hops) {
+                attrs.push(("hops", num_hops.to_string()));
+            }
+        }
+        graph.add_edge(pubkeys[&edge.from], pubkeys[&edge.to], attrs);
+    }
+    graph
+}
+
+/// Returns the push overlay for crds values from the given origin, or if
+/// None, the union of overlays across all origins where edges are labeled by
+/// the number of origins they are part of.
+pub fn get_push_overlay(nodes: &[Node], origin: Option<NodeId>, config: &Config) -> Graph {
+    let mut graph = Graph::default();
+    for node in nodes {
+        graph.add_node(node.pubkey, node.stake);
+    }
+    let pubkeys: HashMap<NodeId, Pubkey> =
+        nodes.iter().map(|node| (node.id, node.pubkey)).collect();
+    match origin {
+        Some(origin) => {
+            for node in nodes {
+                for peer in node.get_push_peers(origin, config) {
+                    graph.add_edge(node.pubkey, pubkeys[&peer], Vec::default());
+                }
+            }
+        }
+        None => {
+            for node in nodes {
+                let counts = nodes
+                    .iter()
+                    .flat_map(|origin| node.get_push_peers(origin.id, config))
+                    .counts();
+                for (peer, num_origins) in counts {
+                    graph.add_edge(
+                        node.pubkey,
+                        pubkeys[&peer],
+                        vec![("num_origins", num_origins.to_string())],
+                    );
+                }
+            }
+        }
+    }
+    graph
 }
 
 /// Returns most recent CRDS table across all nodes.
@@ -437,3 +1143,348 @@ where
     }
     out
 }
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        crate::{
+            freshness::get_node_freshness,
+            simulation::{ClusterSource, Simulation},
+        },
+        solana_sdk::native_token::LAMPORTS_PER_SOL,
+        std::iter::repeat_with,
+    };
+
+    #[test]
+    fn test_entrypoint_discovery() {
+        const NUM_NODES: usize = 20;
+        let config = Config {
+            discovery: Discovery::Entrypoints { num_entrypoints: 2 },
+            ..Config::new_for_tests()
+        };
+        let stakes = (1..=NUM_NODES as u64)
+            .map(|k| (Pubkey::new_unique(), k * 1_000_000_000))
+            .collect();
+        let mut simulation = Simulation::builder()
+            .cluster(ClusterSource::Stakes(stakes))
+            .config(config)
+            .seed(42)
+            .build()
+            .unwrap();
+        simulation.step().unwrap();
+        let entrypoints = simulation.node(NodeId(0)).unwrap().entrypoints().to_vec();
+        assert_eq!(entrypoints.len(), 2);
+        // Active sets are initially sampled from the entrypoints only.
+        for k in 0..NUM_NODES {
+            let node = simulation.node(NodeId::from(k)).unwrap();
+            assert_eq!(node.entrypoints(), entrypoints);
+            assert!((0..NUM_NODES)
+                .map(NodeId::from)
+                .filter(|other| node.active_set.contains(*other))
+                .all(|other| entrypoints.contains(&other)));
+        }
+        for _ in 0..50 {
+            simulation.step().unwrap();
+        }
+        let nodes = simulation.into_nodes().unwrap();
+        for node in &nodes {
+            assert_eq!(node.origins.len(), NUM_NODES - 1);
+            assert!(node.discovery_round().unwrap() > 1);
+        }
+        // Once discovered, active sets span the cluster.
+        let num_active = (0..NUM_NODES)
+            .map(NodeId::from)
+            .filter(|&other| nodes.iter().any(|node| node.active_set.contains(other)))
+            .count();
+        assert!(num_active > NUM_NODES / 2, "{num_active}");
+    }
+
+    #[test]
+    fn test_purge() {
+        const MS: u64 = 1000; // us
+        let config = Config {
+            crds_values: "contact-info:1:250:1,vote:4:600:1".parse().unwrap(),
+            crds_timeout_staked: Duration::from_secs(60),
+            crds_timeout_unstaked: Duration::from_secs(1),
+            ..Config::new_for_tests()
+        };
+        let mut rng = ChaChaRng::seed_from_u64(42);
+        let stakes = [1_000_000_000, 0, 0, 1_000_000_000];
+        let nodes = stakes.iter().map(|&stake| (Pubkey::new_unique(), stake));
+        let mut nodes = make_gossip_nodes(&mut rng, nodes, &config);
+        let (mut node, sender) = nodes.pop().unwrap();
+        let now = timestamp();
+        node.refresh_entries(&mut rng, &config, &());
+        let push = |origin, kind, wallclock| {
+            let packet = Packet::Push {
+                from: NodeId(0),
+                key: CrdsKey::new(NodeId(origin), kind, 0),
+                ordinal: 1,
+                wallclock,
+                timestamp: now,
+            };
+            sender.send(Arc::new(packet)).unwrap();
+        };
+        // Staked origin without contact info.
+        push(0, CrdsValueType::Vote, now - 500 * MS);
+        // Unstaked origin with contact info newer than its vote.
+        push(1, CrdsValueType::Vote, now - 500 * MS);
+        push(1, CrdsValueType::ContactInfo, now);
+        // Unstaked origin without contact info.
+        push(2, CrdsValueType::Vote, now - 500 * MS);
+        // Expired values are not inserted.
+        push(2, CrdsValueType::Vote, now - 2000 * MS);
+        node.consume_packets(&config, &());
+        assert_eq!(node.stats.num_push_expired, 1);
+        let num_own = node
+            .table
+            .keys()
+            .filter(|key| key.origin == node.id)
+            .count();
+        assert!(num_own > 0);
+        assert_eq!(node.table.len(), num_own + 4);
+        let table = get_crds_table([&node]);
+        // Only the unstaked vote without contact info has timed out.
+        node.purge(&config, now + 700 * MS);
+        assert_eq!(node.stats.num_crds_purged, 1);
+        assert!(!node.table.keys().any(|key| key.origin == NodeId(2)));
+        assert_eq!(node.table.len(), num_own + 3);
+        // Purged values count as misses.
+        let freshness = get_node_freshness(&node, &table, &stakes);
+        assert_eq!(freshness.num_hits, num_own + 3);
+        // Unstaked contact info times out along with the vote, while own
+        // values are never purged.
+        node.purge(&config, now + 10_000 * MS);
+        assert_eq!(node.stats.num_crds_purged, 3);
+        assert_eq!(node.table.len(), num_own + 1);
+        node.purge(&config, now + 120_000 * MS);
+        assert_eq!(node.stats.num_crds_purged, 4);
+        assert_eq!(node.table.len(), num_own);
+    }
+
+    #[test]
+    fn test_send_prunes() {
+        const NUM_NODES: usize = 12;
+        let config = Config {
+            gossip_prune_max_origins: 2,
+            ..Config::new_for_tests()
+        };
+        let mut rng = ChaChaRng::seed_from_u64(42);
+        let stakes = repeat_with(|| (Pubkey::new_unique(), LAMPORTS_PER_SOL)).take(NUM_NODES);
+        let (mut nodes, senders): (Vec<_>, Vec<_>) = make_gossip_nodes(&mut rng, stakes, &config)
+            .into_iter()
+            .unzip();
+        let router = Router::new(0.0, senders.iter().cloned()).unwrap();
+        let origins: Vec<_> = (1..6).map(NodeId::from).collect();
+        let relayers: Vec<_> = (6..10).map(NodeId::from).collect();
+        let now = timestamp();
+        for &origin in &origins {
+            for index in 0..4 {
+                for &from in &relayers {
+                    let packet = Packet::Push {
+                        from,
+                        key: CrdsKey::new(origin, CrdsValueType::Vote, index),
+                        ordinal: 1,
+                        wallclock: now,
+                        timestamp: now,
+                    };
+                    senders[0].send(Arc::new(packet)).unwrap();
+                }
+            }
+        }
+        nodes[0].consume_packets(&config, &());
+        nodes[0]
+            .send_prunes(&mut rng, origins.iter().copied(), &config, &router, &())
+            .unwrap();
+        // All but the first min_ingress_nodes relayers are pruned for each
+        // origin, in packets of at most gossip_prune_max_origins origins.
+        let pruned = &relayers[config.gossip_prune_min_ingress_nodes..];
+        assert_eq!(nodes[0].stats.num_prune_packets_sent, 3 * pruned.len());
+        for (k, node) in nodes.iter().enumerate() {
+            let packets: Vec<_> = node.receiver.try_iter().collect();
+            if !pruned.contains(&NodeId::from(k)) {
+                assert!(packets.is_empty());
+                continue;
+            }
+            let mut num_origins = Vec::new();
+            for packet in &packets {
+                let Packet::Prune {
+                    from,
+                    destination,
+                    origins: ref chunk,
+                    wallclock,
+                } = **packet else {
+                    panic!("unexpected packet: {packet:?}");
+                };
+                assert_eq!(from, NodeId(0));
+                assert_eq!(destination, NodeId::from(k));
+                assert!((now..=timestamp()).contains(&wallclock));
+                num_origins.push(chunk.len());
+            }
+            assert_eq!(num_origins, [2, 2, 1]);
+            let chunks = packets.iter().flat_map(|packet| match &**packet {
+                Packet::Prune { origins, .. } => origins.iter().copied(),
+                Packet::Push { .. } => unreachable!(),
+            });
+            assert!(chunks.sorted_unstable().eq(origins.iter().copied()));
+        }
+    }
+
+    #[test]
+    fn test_consume_prunes() {
+        const NUM_NODES: usize = 40;
+        let config = Config {
+            gossip_push_fanout: 1.0,
+            gossip_prune_timeout: Duration::from_secs(60),
+            ..Config::new_for_tests()
+        };
+        let mut rng = ChaChaRng::seed_from_u64(42);
+        let stakes = repeat_with(|| (Pubkey::new_unique(), LAMPORTS_PER_SOL)).take(NUM_NODES);
+        let mut nodes = make_gossip_nodes(&mut rng, stakes, &config);
+        let (mut node, sender) = nodes.swap_remove(0);
+        node.rotate_active_set(&mut rng, &config);
+        let origin = NodeId::from(NUM_NODES - 1);
+        let get_nodes = |node: &Node| -> Vec<NodeId> {
+            node.active_set
+                .get_nodes(node.id, origin, |_| false, &node.stakes)
+                .collect()
+        };
+        let peers = get_nodes(&node);
+        let peer = peers[0];
+        let inactive = (1..NUM_NODES)
+            .map(NodeId::from)
+            .find(|&other| other != origin && !node.active_set.contains(other))
+            .unwrap();
+        let prune = |from, destination, age: Duration| {
+            let packet = Packet::Prune {
+                from,
+                destination,
+                origins: vec![origin],
+                wallclock: timestamp() - age.as_micros() as u64,
+            };
+            sender.send(Arc::new(packet)).unwrap();
+        };
+        // Rejected prunes leave the active set unchanged.
+        prune(peer, peer, Duration::ZERO);
+        prune(peer, node.id, Duration::from_secs(61));
+        prune(inactive, node.id, Duration::ZERO);
+        let out = node.consume_packets(&config, &());
+        assert_eq!(out.num_prunes, 3);
+        assert_eq!(node.stats.num_prune_packets_received, 3);
+        assert_eq!(node.stats.num_prunes_bad_destination, 1);
+        assert_eq!(node.stats.num_prunes_stale, 1);
+        assert_eq!(node.stats.num_prunes_inactive, 1);
+        assert_eq!(get_nodes(&node), peers);
+        // A prune within the timeout is applied.
+        prune(peer, node.id, Duration::from_secs(59));
+        node.consume_packets(&config, &());
+        assert_eq!(node.stats.num_prune_packets_received, 4);
+        assert_eq!(node.stats.num_prunes_bad_destination, 1);
+        assert_eq!(node.stats.num_prunes_stale, 1);
+        assert_eq!(node.stats.num_prunes_inactive, 1);
+        assert_eq!(get_nodes(&node), peers[1..]);
+    }
+
+    #[test]
+    fn test_diverging_stake_views() {
+        const NUM_NODES: usize = 6;
+        let config = Config::new_for_tests();
+        let origin = NodeId(1);
+        let peer = NodeId(2);
+        // Nodes 0 and 1 are staked, the rest relay values from node 1.
+        let stakes: Vec<_> = (0..NUM_NODES)
+            .map(|k| if k < 2 { 100 } else { 1 } * LAMPORTS_PER_SOL)
+            .collect();
+        let cluster_stakes: Arc<[u64]> = stakes.iter().copied().collect();
+        // The origin is unstaked in the diverging view.
+        let view: Arc<[u64]> = stakes
+            .iter()
+            .enumerate()
+            .map(|(k, &stake)| if k == origin.index() { 0 } else { stake })
+            .collect();
+        let make_node = || {
+            let mut rng = ChaChaRng::seed_from_u64(42);
+            let nodes = stakes.iter().map(|&stake| (Pubkey::new_unique(), stake));
+            let (mut nodes, senders): (Vec<_>, Vec<_>) =
+                make_gossip_nodes(&mut rng, nodes, &config)
+                    .into_iter()
+                    .unzip();
+            let mut node = nodes.swap_remove(0);
+            node.rotate_active_set(&mut rng, &config);
+            (node, nodes, senders)
+        };
+        // Two identical nodes, one of which moves to the diverging view.
+        let (mut node, _nodes, senders) = make_node();
+        let (mut other, ..) = make_node();
+        other.set_epoch_stakes(&config, &view);
+        assert_eq!(other.stakes(), &view[..]);
+        let router = Router::new(0.0, senders).unwrap();
+        let get_nodes = |node: &Node| -> Vec<NodeId> {
+            node.active_set
+                .get_nodes(node.id, origin, |_| false, &node.stakes)
+                .collect()
+        };
+        // Each entry holds all other nodes, but the origin maps to different
+        // entries in the two views.
+        assert_eq!(
+            get_nodes(&other),
+            node.active_set
+                .get_nodes(node.id, origin, |_| false, &view)
+                .collect::<Vec<_>>()
+        );
+        assert_ne!(get_nodes(&node), get_nodes(&other));
+        assert!(get_nodes(&node).contains(&peer));
+        // Prunes are recorded in the entry picked by each node's own view.
+        let now = timestamp();
+        let prune = Arc::new(Packet::Prune {
+            from: peer,
+            destination: NodeId(0),
+            origins: vec![origin],
+            wallclock: now,
+        });
+        // Pushes of the origin's values relayed by all other nodes.
+        let pushes: Vec<_> = (0..4)
+            .flat_map(|index| {
+                (2..NUM_NODES).map(move |from| {
+                    Arc::new(Packet::Push {
+                        from: NodeId::from(from),
+                        key: CrdsKey::new(origin, CrdsValueType::Vote, index),
+                        ordinal: 1,
+                        wallclock: now,
+                        timestamp: now,
+                    })
+                })
+            })
+            .collect();
+        for node in [&mut node, &mut other] {
+            let (sender, receiver) = crossbeam_channel::unbounded();
+            node.receiver = receiver;
+            sender.send(prune.clone()).unwrap();
+            for push in &pushes {
+                sender.send(push.clone()).unwrap();
+            }
+            node.consume_packets(&config, &());
+            assert!(!get_nodes(node).contains(&peer));
+        }
+        // Once back on the cluster stakes, the origin maps to an entry where
+        // the peer has not been pruned.
+        other.set_epoch_stakes(&config, &cluster_stakes);
+        assert!(!get_nodes(&node).contains(&peer));
+        assert!(get_nodes(&other).contains(&peer));
+        // With the origin staked, relaying nodes do not reach the minimum
+        // ingress stake and none are pruned. With the origin unstaked, all
+        // but the minimum number of ingress nodes are pruned.
+        let mut rng = ChaChaRng::seed_from_u64(42);
+        other.set_epoch_stakes(&config, &view);
+        for node in [&mut node, &mut other] {
+            node.send_prunes(&mut rng, [origin], &config, &router, &())
+                .unwrap();
+        }
+        assert_eq!(node.stats.num_prune_packets_sent, 0);
+        assert_eq!(
+            other.stats.num_prune_packets_sent,
+            NUM_NODES - 2 - config.gossip_prune_min_ingress_nodes
+        );
+    }
+}
@@ -1,40 +1,211 @@
 use {
     clap::{crate_description, crate_name, App, Arg},
     cluster_mocks::{
-        gossip::{get_crds_table, make_gossip_cluster, Config, CrdsEntry, Node, Packet},
-        Error, Router, API_MAINNET_BETA,
+        checkpoint::Checkpoint,
+        crds_value::CrdsValueModel,
+        freshness::{
+            get_node_freshness, get_origin_freshness, get_type_freshness, BucketFreshness,
+            FreshnessSummary, NodeFreshness, OriginFreshness, TypeFreshness, QUANTILES,
+        },
+        gossip::{
+            get_crds_table, get_propagation_graph, get_push_overlay, Config, CrdsKey, Discovery,
+            Node, NodeStats, Trace, CRDS_UNIQUE_PUBKEY_CAPACITY,
+        },
+        graph::Graph,
+        push_active_set::get_stake_bucket,
+        received_cache::ReceivedCacheScorer,
+        scheduler::RoundStats,
+        simulation::{ClusterSource, Simulation},
+        stakes::StakeViewModel,
+        NodeId, API_MAINNET_BETA,
     },
+    itertools::Itertools,
     log::info,
-    rand::seq::SliceRandom,
     rayon::{prelude::*, ThreadPoolBuilder},
-    solana_client::rpc_client::RpcClient,
     solana_sdk::pubkey::Pubkey,
     std::{
         cmp::Reverse,
         collections::HashMap,
-        sync::{Arc, RwLock, TryLockError},
         time::{Duration, Instant},
     },
 };
 
-fn run_gossip(
-    config: &Config,
-    nodes: &[RwLock<Node>],
-    stakes: &HashMap<Pubkey, /*stake:*/ u64>,
-    router: &Router<Arc<Packet>>,
-) -> Result<(), Error> {
-    let mut rng = rand::thread_rng();
-    let now = Instant::now();
-    while now.elapsed() < config.run_duration {
-        let node = nodes.choose(&mut rng).unwrap();
-        let mut node = match node.try_write() {
-            Ok(node) => node,
-            Err(TryLockError::Poisoned(_)) => return Err(Error::TryLockErrorPoisoned),
-            Err(TryLockError::WouldBlock) => continue,
+fn print_overlay_metrics(graph: &Graph, min_cut_fraction: f64) {
+    // In/out degree distribution by stake bucket.
+    let degrees = graph
+        .nodes()
+        .zip(graph.degrees())
+        .map(|((_pubkey, stake), degrees)| (get_stake_bucket(Some(stake)), degrees))
+        .into_group_map();
+    println!("bucket | nodes | in-degree: min  mean   max | out-degree: min  mean   max");
+    println!("--------------------------------------------------------------------------");
+    for (bucket, degrees) in degrees
+        .into_iter()
+        .sorted_unstable_by_key(|(k, _)| Reverse(*k))
+    {
+        let get_stats = |degrees: Vec<usize>| {
+            let min = degrees.iter().min().copied().unwrap_or_default();
+            let max = degrees.iter().max().copied().unwrap_or_default();
+            let mean = degrees.iter().sum::<usize>() as f64 / degrees.len() as f64;
+            (min, mean, max)
         };
-        node.run_gossip(&mut rng, config, stakes, router)?;
+        let (in_min, in_mean, in_max) = get_stats(degrees.iter().map(|d| d.0).collect());
+        let (out_min, out_mean, out_max) = get_stats(degrees.iter().map(|d| d.1).collect());
+        println!(
+            "{:6} | {:5} |            {:4} {:5.1} {:5} |             {:4} {:5.1} {:5}",
+            bucket,
+            degrees.len(),
+            in_min,
+            in_mean,
+            in_max,
+            out_min,
+            out_mean,
+            out_max,
+        );
+    }
+    let components = graph.strongly_connected_components();
+    info!(
+        "strongly connected components: {}, sizes: {:?}",
+        components.len(),
+        components.iter().map(Vec::len).take(10).collect::<Vec<_>>()
+    );
+    match graph.diameter() {
+        None => info!("diameter: inf"),
+        Some(diameter) => info!("diameter: {}", diameter),
+    }
+    // Min-cut between the top and bottom fraction of nodes by stake, which
+    // needs at least one node on each side.
+    let nodes: Vec<Pubkey> = graph
+        .nodes()
+        .sorted_unstable_by_key(|(_pubkey, stake)| Reverse(**stake))
+        .map(|(pubkey, _stake)| *pubkey)
+        .collect();
+    if nodes.len() < 2 {
+        return;
+    }
+    let size = ((nodes.len() as f64 * min_cut_fraction) as usize).clamp(1, nodes.len() / 2);
+    let (high, low) = (&nodes[..size], &nodes[nodes.len() - size..]);
+    info!(
+        "min-cut between {} high and {} low staked nodes: high -> low: {}, low -> high: {}",
+        size,
+        size,
+        graph.min_cut(high, low),
+        graph.min_cut(low, high),
+    );
+}
+
+fn print_freshness_summary(freshness: &[NodeFreshness]) {
+    println!(
+        "freshness            |   mean |    min | {}",
+        QUANTILES
+            .iter()
+            .map(|q| format!("p{:<4}", q * 100.0))
+            .join(" | ")
+    );
+    println!("---------------------------------------------------------------------------");
+    // Each row weights freshness by origin stake and/or observer stake.
+    let rows = [
+        ("unweighted", false, false),
+        ("origin stake", true, false),
+        ("observer stake", false, true),
+        ("origin x observer", true, true),
+    ];
+    for (name, origin_stake, observer_stake) in rows {
+        let get_value = |f: &NodeFreshness| {
+            let value = if origin_stake {
+                f.stake_weighted_freshness
+            } else {
+                f.freshness
+            };
+            (value, if observer_stake { f.stake } else { 1 })
+        };
+        let Some(summary) = FreshnessSummary::new(freshness.iter().map(get_value)) else {
+            continue;
+        };
+        println!(
+            "{:20} | {:5.1}% | {:5.1}% | {}",
+            name,
+            summary.mean * 100.0,
+            summary.min * 100.0,
+            summary
+                .quantiles
+                .iter()
+                .map(|value| format!("{:4.1}%", value * 100.0))
+                .join(" | "),
+        );
+    }
+}
+
+// Prints origins whose crds values are the least up to date across nodes.
+fn print_origin_freshness(freshness: Vec<OriginFreshness>, num_worst_origins: usize) {
+    for (name, weighted) in [("unweighted", false), ("stake weighted", true)] {
+        let values = freshness
+            .iter()
+            .map(|f| (f.freshness, if weighted { f.stake } else { 1 }));
+        if let Some(summary) = FreshnessSummary::new(values) {
+            info!(
+                "origin freshness, {}: mean: {:.1}%, min: {:.1}%",
+                name,
+                summary.mean * 100.0,
+                summary.min * 100.0
+            );
+        }
+    }
+    let active_stake: u64 = freshness.iter().map(|f| f.stake).sum();
+    println!("origin   | stake  | bucket |  fresh |  stale | mean lag | max lag");
+    println!("-----------------------------------------------------------------");
+    for f in freshness.iter().take(num_worst_origins) {
+        println!(
+            "{} | {:.3}% | {:6} | {:5.1}% | {:6} | {:8.2} | {:7}",
+            &format!("{}", f.origin)[..8],
+            f.stake as f64 * 100.0 / active_stake.max(1) as f64,
+            get_stake_bucket(Some(&f.stake)),
+            f.freshness * 100.0,
+            f.num_stale,
+            f.mean_lag,
+            f.max_lag,
+        );
+    }
+}
+
+// Prints freshness of each crds value type, unweighted and weighted by origin stake.
+fn print_type_freshness(freshness: &[TypeFreshness], crds_values: &CrdsValueModel) {
+    println!("type            | count |  size | refresh |  values |  fresh | weighted");
+    println!("-------------------------------------------------------------------------");
+    for f in freshness {
+        let params = crds_values.get(f.kind);
+        println!(
+            "{:15} | {:5} | {:5} | {:7.4} | {:7} | {:5.1}% | {:7.1}%",
+            f.kind,
+            params.count,
+            params.size,
+            params.refresh_rate,
+            f.num_values,
+            f.freshness * 100.0,
+            f.stake_weighted_freshness * 100.0,
+        );
+    }
+}
+
+// Prints freshness by stake bucket of observer (rows) x origin (columns).
+fn print_bucket_freshness(freshness: &BucketFreshness, buckets: &[usize]) {
+    println!(
+        "observer \\ origin | {}",
+        buckets
+            .iter()
+            .map(|bucket| format!("{bucket:5}"))
+            .join(" | ")
+    );
+    println!("{}", "-".repeat(19 + 8 * buckets.len()));
+    for &observer in buckets {
+        let row = buckets
+            .iter()
+            .map(|&origin| match freshness.get(observer, origin) {
+                None => String::from("    -"),
+                Some(freshness) => format!("{:5.1}", freshness * 100.0),
+            });
+        println!("{:17} | {}", observer, row.format(" | "));
     }
-    Ok(())
 }
 
 fn main() {
@@ -93,6 +264,73 @@ fn main() {
                 .default_value("3")
                 .help("Min ingress number of nodes to keep when pruning received-cache"),
         )
+        .arg(
+            Arg::with_name("gossip_prune_max_origins")
+                .long("gossip-prune-max-origins")
+                .takes_value(true)
+                .default_value("32")
+                .help("Maximum number of origins in each prune packet"),
+        )
+        .arg(
+            Arg::with_name("gossip_prune_timeout")
+                .long("gossip-prune-timeout")
+                .takes_value(true)
+                // The validator uses 500ms, a few iterations of its gossip
+                // loop. Simulated rounds take far longer, ~0.5s with 500
+                // nodes and more with larger clusters, so the default allows
+                // for several rounds.
+                .default_value("5000")
+                .help("Prune packets older than this are rejected [ms]"),
+        )
+        .arg(
+            Arg::with_name("gossip_prune_stake_threshold_pct")
+                .long("gossip-prune-stake-threshold-pct")
+                .takes_value(true)
+                .default_value("0.15")
+                .help("Fraction of min stake of {this node, origin} to keep when pruning"),
+        )
+        .arg(
+            Arg::with_name("received_cache_capacity")
+                .long("received-cache-capacity")
+                .takes_value(true)
+                .help("Number of origins tracked by received-cache"),
+        )
+        .arg(
+            Arg::with_name("received_cache_min_num_upserts")
+                .long("received-cache-min-num-upserts")
+                .takes_value(true)
+                .default_value("20")
+                .help("Min number of upserts before a received-cache entry can be pruned"),
+        )
+        .arg(
+            Arg::with_name("received_cache_entry_capacity")
+                .long("received-cache-entry-capacity")
+                .takes_value(true)
+                .default_value("50")
+                .help("Max number of nodes tracked by each received-cache entry"),
+        )
+        .arg(
+            Arg::with_name("received_cache_num_dups_threshold")
+                .long("received-cache-num-dups-threshold")
+                .takes_value(true)
+                .default_value("2")
+                .help("Number of duplicates before which a message is counted as timely"),
+        )
+        .arg(
+            Arg::with_name("received_cache_scorer")
+                .long("received-cache-scorer")
+                .takes_value(true)
+                .possible_values(["num-dups", "arrival-rank", "arrival-delay"])
+                .default_value("num-dups")
+                .help("How received-cache scores timeliness of delivered messages"),
+        )
+        .arg(
+            Arg::with_name("received_cache_max_delay")
+                .long("received-cache-max-delay")
+                .takes_value(true)
+                .default_value("50")
+                .help("Delay after the first delivery beyond which a message does not score [ms]"),
+        )
         .arg(
             Arg::with_name("gossip_push_capacity")
                 .long("gossip-push-capacity")
@@ -108,18 +346,58 @@ fn main() {
                 .help("packet drop probability"),
         )
         .arg(
-            Arg::with_name("num_crds")
-                .long("num-crds")
+            Arg::with_name("crds_values")
+                .long("crds-values")
+                .takes_value(true)
+                .default_value("mainnet")
+                .help(
+                    "CRDS values per node, as a comma separated list of \"mainnet\" or \
+                    <type>:<count>:<size>:<refresh rate>, e.g. mainnet,vote:32:600:0.5",
+                ),
+        )
+        .arg(
+            Arg::with_name("num_entrypoints")
+                .long("num-entrypoints")
+                .takes_value(true)
+                .help(
+                    "Nodes start out knowing only this many entrypoints and discover \
+                    the cluster through gossip, instead of knowing all nodes",
+                ),
+        )
+        .arg(
+            Arg::with_name("stake_view_known_fraction")
+                .long("stake-view-known-fraction")
                 .takes_value(true)
-                .default_value("256")
-                .help("num of CRDS values per node"),
+                .default_value("1.0")
+                .help("Fraction of other nodes whose stake each node knows"),
         )
         .arg(
-            Arg::with_name("refresh_rate")
-                .long("refresh-rate")
+            Arg::with_name("stake_view_noise")
+                .long("stake-view-noise")
                 .takes_value(true)
-                .default_value("4")
-                .help("number of new CRDS value to generate per gossip round"),
+                .default_value("0.0")
+                .help("Each node sees stakes scaled by a random factor in [1 - noise, 1 + noise]"),
+        )
+        .arg(
+            Arg::with_name("crds_purge_rounds")
+                .long("crds-purge-rounds")
+                .takes_value(true)
+                .default_value("10")
+                .help("Number of gossip rounds between purges of timed out CRDS values"),
+        )
+        .arg(
+            Arg::with_name("crds_timeout_staked")
+                .long("crds-timeout-staked")
+                .takes_value(true)
+                .default_value("172800000")
+                .help("CRDS values from staked origins older than this are purged [ms]"),
+        )
+        .arg(
+            Arg::with_name("crds_timeout_unstaked")
+                .long("crds-timeout-unstaked")
+                .takes_value(true)
+                .default_value("15000")
+                .help("CRDS values from unstaked origins older than this are purged [ms]"),
         )
         .arg(
             Arg::with_name("warm_up_rounds")
@@ -127,14 +405,97 @@ fn main() {
                 .takes_value(true)
                 .help("number of gossip rounds before collecting stats"),
         )
+        .arg(
+            Arg::with_name("num_worst_origins")
+                .long("num-worst-origins")
+                .takes_value(true)
+                .default_value("20")
+                .help("Number of least up to date origins to report"),
+        )
+        .arg(
+            Arg::with_name("overlay_metrics")
+                .long("overlay-metrics")
+                .takes_value(false)
+                .help("Print metrics of the push overlay at the end of simulation"),
+        )
+        .arg(
+            Arg::with_name("overlay_out")
+                .long("overlay-out")
+                .value_name("PATH")
+                .takes_value(true)
+                .help("Write push overlay at the end of simulation to .dot or .graphml file"),
+        )
+        .arg(
+            Arg::with_name("overlay_origin")
+                .long("overlay-origin")
+                .value_name("PUBKEY")
+                .takes_value(true)
+                .help("Origin of crds values for the push overlay; aggregates all origins if not specified"),
+        )
+        .arg(
+            Arg::with_name("overlay_min_cut_fraction")
+                .long("overlay-min-cut-fraction")
+                .takes_value(true)
+                .default_value("0.25")
+                .help("Fraction of highest and lowest staked nodes to compute the min-cut between"),
+        )
+        .arg(
+            Arg::with_name("checkpoint_out")
+                .long("checkpoint-out")
+                .value_name("PATH")
+                .takes_value(true)
+                .help("Write checkpoint of the cluster state at the end of the run"),
+        )
+        .arg(
+            Arg::with_name("resume_from")
+                .long("resume-from")
+                .value_name("PATH")
+                .takes_value(true)
+                .help("Resume from a checkpoint instead of a cold cluster fetched over rpc"),
+        )
+        .arg(
+            Arg::with_name("trace_out")
+                .long("trace-out")
+                .value_name("PATH")
+                .takes_value(true)
+                .help("Write propagation graph of the traced crds value to .dot or .graphml file"),
+        )
+        .arg(
+            Arg::with_name("trace_origin")
+                .long("trace-origin")
+                .value_name("PUBKEY")
+                .takes_value(true)
+                .requires("trace_out")
+                .help("Origin of the traced crds value; defaults to the highest staked node"),
+        )
+        .arg(
+            Arg::with_name("trace_type")
+                .long("trace-type")
+                .takes_value(true)
+                .default_value("contact-info")
+                .help("Type of the traced crds value"),
+        )
+        .arg(
+            Arg::with_name("trace_index")
+                .long("trace-index")
+                .takes_value(true)
+                .default_value("0")
+                .help("Index of the traced crds value"),
+        )
+        .arg(
+            Arg::with_name("trace_ordinal")
+                .long("trace-ordinal")
+                .takes_value(true)
+                .default_value("1")
+                .help("Ordinal of the traced crds value"),
+        )
         .get_matches();
 
     let json_rpc_url =
         cluster_mocks::get_json_rpc_url(matches.value_of("json_rpc_url").unwrap_or_default());
     info!("json_rpc_url: {}", json_rpc_url);
-    let rpc_client = RpcClient::new(json_rpc_url);
-    let config = {
-        let num_crds = matches.value_of_t_or_exit("num_crds");
+    let mut config = {
+        let crds_values: CrdsValueModel = matches.value_of_t_or_exit("crds_values");
         let gossip_push_fanout = matches.value_of_t_or_exit("gossip_push_fanout");
         Config {
             gossip_push_fanout,
@@ -144,79 +505,231 @@ fn main() {
             rotate_active_set_rounds: matches.value_of_t_or_exit("rotate_active_set_rounds"),
             gossip_prune_min_ingress_nodes: matches
                 .value_of_t_or_exit("gossip_prune_min_ingress_nodes"),
+            gossip_prune_max_origins: matches.value_of_t_or_exit("gossip_prune_max_origins"),
+            gossip_prune_timeout: Duration::from_millis(
+                matches.value_of_t_or_exit("gossip_prune_timeout"),
+            ),
+            gossip_prune_stake_threshold_pct: matches
+                .value_of_t_or_exit("gossip_prune_stake_threshold_pct"),
+            received_cache_capacity: matches
+                .value_of_t("received_cache_capacity")
+                .unwrap_or(2 * CRDS_UNIQUE_PUBKEY_CAPACITY),
+            received_cache_min_num_upserts: matches
+                .value_of_t_or_exit("received_cache_min_num_upserts"),
+            received_cache_entry_capacity: matches
+                .value_of_t_or_exit("received_cache_entry_capacity"),
+            received_cache_num_dups_threshold: matches
+                .value_of_t_or_exit("received_cache_num_dups_threshold"),
+            received_cache_scorer: match matches.value_of("received_cache_scorer").unwrap() {
+                "num-dups" => ReceivedCacheScorer::NumDups,
+                "arrival-rank" => ReceivedCacheScorer::ArrivalRank,
+                "arrival-delay" => ReceivedCacheScorer::ArrivalDelay {
+                    max_delay: Duration::from_millis(
+                        matches.value_of_t_or_exit("received_cache_max_delay"),
+                    ),
+                },
+                scorer => panic!("invalid received-cache scorer: {scorer}"),
+            },
             gossip_push_capacity: matches.value_of_t_or_exit("gossip_push_capacity"),
             packet_drop_rate: matches.value_of_t_or_exit("packet_drop_rate"),
-            num_crds,
-            refresh_rate: matches.value_of_t_or_exit("refresh_rate"),
+            crds_values,
+            discovery: match matches.value_of("num_entrypoints") {
+                None => Discovery::Global,
+                Some(_) => Discovery::Entrypoints {
+                    num_entrypoints: matches.value_of_t_or_exit("num_entrypoints"),
+                },
+            },
+            stake_view: StakeViewModel {
+                known_fraction: matches.value_of_t_or_exit("stake_view_known_fraction"),
+                noise: matches.value_of_t_or_exit("stake_view_noise"),
+                // Stakes do not change during the run, so the epoch lag is
+                // only reachable through Simulation::set_epoch_stakes.
+                max_lag_rounds: 0,
+            },
+            crds_purge_rounds: matches.value_of_t_or_exit("crds_purge_rounds"),
+            crds_timeout_staked: Duration::from_millis(
+                matches.value_of_t_or_exit("crds_timeout_staked"),
+            ),
+            crds_timeout_unstaked: Duration::from_millis(
+                matches.value_of_t_or_exit("crds_timeout_unstaked"),
+            ),
             num_threads: matches
                 .value_of_t("num_threads")
                 .unwrap_or_else(|_| num_cpus::get()),
             run_duration: Duration::from_secs(
                 matches.value_of_t_or_exit::<u64>("run_duration") * 60,
             ),
-            warm_up_rounds: matches.value_of_t("warm_up_rounds").unwrap_or(2 * num_crds),
+            warm_up_rounds: matches
+                .value_of_t("warm_up_rounds")
+                .unwrap_or(2 * crds_values.num_values()),
+            trace: None,
         }
     };
     info!("config: {:#?}", config);
     assert!(config.num_threads > 0);
-    let nodes = make_gossip_cluster(&rpc_client).unwrap();
-    let (nodes, senders): (Vec<_>, Vec<_>) = nodes
-        .into_iter()
-        .map(|(node, sender)| {
-            let pubkey = node.pubkey();
-            (node, (pubkey, sender))
-        })
-        .unzip();
-    let router = Router::new(config.packet_drop_rate, senders).unwrap();
+    assert!(config.gossip_prune_max_origins > 0);
+    assert!(config.crds_purge_rounds > 0);
+    assert!((0.0..=1.0).contains(&config.stake_view.known_fraction));
+    assert!((0.0..=1.0).contains(&config.stake_view.noise));
+    assert!((0.0..=1.0).contains(&config.gossip_prune_stake_threshold_pct));
+    let source = match matches.value_of("resume_from") {
+        None => ClusterSource::Rpc(json_rpc_url.to_string()),
+        Some(path) => {
+            let checkpoint = Checkpoint::read_from_file(path).unwrap();
+            info!("resuming {} nodes from {}", checkpoint.num_nodes(), path);
+            ClusterSource::Checkpoint(checkpoint)
+        }
+    };
+    let mut simulation = Simulation::builder()
+        .cluster(source)
+        .config(config)
+        .build()
+        .unwrap();
     // TODO: remove unstaked here?!
-    let stakes: HashMap<Pubkey, /*stake:*/ u64> = nodes
+    let stakes = simulation.stakes().to_vec();
+    let node_ids: HashMap<Pubkey, NodeId> = simulation
+        .pubkeys()
         .iter()
-        .map(|node| (node.pubkey(), node.stake()))
+        .enumerate()
+        .map(|(k, pubkey)| (*pubkey, NodeId::from(k)))
         .collect();
-    let nodes: Vec<_> = nodes.into_iter().map(RwLock::new).collect();
+    let get_node_id = |arg| {
+        let pubkey: Pubkey = matches.value_of_t_or_exit(arg);
+        *node_ids
+            .get(&pubkey)
+            .unwrap_or_else(|| panic!("node not found: {pubkey}"))
+    };
+    if matches.is_present("trace_out") {
+        let origin = if matches.is_present("trace_origin") {
+            get_node_id("trace_origin")
+        } else {
+            let k = (0..stakes.len()).max_by_key(|&k| stakes[k]).unwrap();
+            NodeId::from(k)
+        };
+        let kind = matches.value_of_t_or_exit("trace_type");
+        let index = matches.value_of_t_or_exit("trace_index");
+        assert!(index < config.crds_values.get(kind).count);
+        config.trace = Some(Trace {
+            key: CrdsKey::new(origin, kind, index),
+            ordinal: matches.value_of_t_or_exit("trace_ordinal"),
+        });
+        info!("trace: {:?}", config.trace);
+        simulation.set_trace(config.trace);
+    }
+    simulation
+        .run_until(Instant::now() + config.run_duration)
+        .unwrap();
+    info!("run_gossip done!");
+    if let Some(path) = matches.value_of("checkpoint_out") {
+        simulation
+            .checkpoint()
+            .unwrap()
+            .write_to_file(path)
+            .unwrap();
+        info!("checkpoint written to {}", path);
+    }
+    // Consume packets buffered at each node's receiver channel.
+    let mut nodes = simulation.into_nodes().unwrap();
+    info!("consume_packets done!");
     let thread_pool = ThreadPoolBuilder::new()
         .num_threads(config.num_threads)
         .build()
         .unwrap();
-    thread_pool
-        .broadcast(|_ctx| run_gossip(&config, &nodes, &stakes, &router))
-        .into_iter()
-        .collect::<Result<Vec<()>, Error>>()
-        .unwrap();
-    info!("run_gossip done!");
-    let mut nodes: Vec<_> = nodes
-        .into_iter()
-        .map(RwLock::into_inner)
-        .collect::<Result<_, _>>()
-        .unwrap();
-    // Consume packets buffered at each node's receiver channel.
-    thread_pool.install(|| {
-        nodes.par_iter_mut().for_each(|node| {
-            node.consume_packets(&stakes);
-        })
+    let stats = nodes.iter().fold(NodeStats::default(), |mut stats, node| {
+        stats += node.stats();
+        stats
     });
-    info!("consume_packets done!");
+    info!("node stats: {:#?}", stats);
+    info!(
+        "rounds: {:?}",
+        RoundStats::new(nodes.iter().map(Node::num_gossip_rounds))
+    );
+    info!(
+        "discovery: {}/{} nodes discovered the cluster, rounds: {:?}",
+        nodes.iter().filter_map(Node::discovery_round).count(),
+        nodes.len(),
+        RoundStats::new(nodes.iter().filter_map(Node::discovery_round))
+    );
+    if let Some(trace) = &config.trace {
+        let graph = get_propagation_graph(&nodes, trace);
+        let num_reached = graph
+            .edges()
+            .iter()
+            .filter(|edge| edge.attrs.contains(&("kind", String::from("first"))))
+            .count();
+        info!(
+            "trace: reached {} nodes, {} deliveries",
+            num_reached,
+            graph.edges().len()
+        );
+        let path = matches.value_of("trace_out").unwrap();
+        graph.write_to_file(path).unwrap();
+        info!("propagation graph written to {}", path);
+    }
+    if matches.is_present("overlay_metrics") || matches.is_present("overlay_out") {
+        let origin = matches
+            .is_present("overlay_origin")
+            .then(|| get_node_id("overlay_origin"));
+        let graph = get_push_overlay(&nodes, origin, &config);
+        info!(
+            "push overlay: {} nodes, {} edges",
+            graph.nodes().count(),
+            graph.edges().len()
+        );
+        if let Some(path) = matches.value_of("overlay_out") {
+            graph.write_to_file(path).unwrap();
+            info!("push overlay written to {}", path);
+        }
+        if matches.is_present("overlay_metrics") {
+            let min_cut_fraction = matches.value_of_t_or_exit("overlay_min_cut_fraction");
+            assert!((0.0..=0.5).contains(&min_cut_fraction));
+            print_overlay_metrics(&graph, min_cut_fraction);
+        }
+    }
     // Obtain most recent crds table across all nodes.
     let table = get_crds_table(&nodes);
     info!("num crds entries per node: {}", table.len() / nodes.len());
     // For each node compute how fresh its CRDS table is.
     nodes.sort_unstable_by_key(|node| Reverse(node.stake()));
     let active_stake: u64 = nodes.iter().map(|node| node.stake()).sum();
-    println!("node     | stake | rounds |   table | crds");
-    println!("-------------------------------------------");
-    for node in &nodes {
-        let node_table = node.table();
-        let num_hits = table
-            .iter()
-            .filter(|(key, ordinal)| node_table.get(key).map(CrdsEntry::ordinal) == Some(**ordinal))
-            .count();
+    let freshness: Vec<NodeFreshness> = thread_pool.install(|| {
+        nodes
+            .par_iter()
+            .map(|node| get_node_freshness(node, &table, &stakes))
+            .collect()
+    });
+    println!("node     | stake | rounds | discovery |   table |   crds | weighted");
+    println!("------------------------------------------------------------------");
+    for (node, freshness) in nodes.iter().zip(&freshness) {
+        let discovery_round = node
+            .discovery_round()
+            .map(|round| round.to_string())
+            .unwrap_or_else(|| String::from("-"));
         println!(
-            "{} | {:.2}% | {:6} | {:7} | {:2}%",
+            "{} | {:.2}% | {:6} | {:>9} | {:7} | {:5.1}% | {:7.1}%",
             &format!("{}", node.pubkey())[..8],
             node.stake() as f64 * 100.0 / active_stake as f64,
             node.num_gossip_rounds(),
-            node_table.len(),
-            num_hits * 100 / table.len(),
+            discovery_round,
+            node.table().len(),
+            freshness.freshness * 100.0,
+            freshness.stake_weighted_freshness * 100.0,
         );
     }
+    print_freshness_summary(&freshness);
+    print_type_freshness(
+        &get_type_freshness(&nodes, &table, &stakes),
+        &config.crds_values,
+    );
+    let buckets: Vec<usize> = nodes
+        .iter()
+        .map(|node| get_stake_bucket(Some(&node.stake())))
+        .sorted_unstable_by_key(|&bucket| Reverse(bucket))
+        .dedup()
+        .collect();
+    print_bucket_freshness(&BucketFreshness::new(&nodes, &table, &stakes), &buckets);
+    print_origin_freshness(
+        get_origin_freshness(&nodes, &table, &stakes),
+        matches.value_of_t_or_exit("num_worst_origins"),
+    );
 }
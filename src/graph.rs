@@ -0,0 +1,393 @@
+use {
+    crate::Error,
+    indexmap::{IndexMap, IndexSet},
+    solana_sdk::pubkey::Pubkey,
+    std::{
+        collections::VecDeque,
+        fs::File,
+        io::{BufWriter, Write},
+        path::Path,
+    },
+};
+
+/// Directed graph over gossip nodes, with node stakes and arbitrary edge
+/// attributes, which can be exported for visualization.
+#[derive(Debug, Default)]
+pub struct Graph {
+    nodes: IndexMap<Pubkey, /*stake:*/ u64>,
+    edges: Vec<Edge>,
+}
+
+#[derive(Clone, Debug)]
+pub struct Edge {
+    pub from: Pubkey,
+    pub to: Pubkey,
+    pub attrs: Vec<(/*name:*/ &'static str, /*value:*/ String)>,
+}
+
+impl Graph {
+    pub fn add_node(&mut self, pubkey: Pubkey, stake: u64) {
+        self.nodes.insert(pubkey, stake);
+    }
+
+    /// Adds an edge; end points not already in the graph are added with
+    /// zero stake.
+    pub fn add_edge(&mut self, from: Pubkey, to: Pubkey, attrs: Vec<(&'static str, String)>) {
+        self.nodes.entry(from).or_default();
+        self.nodes.entry(to).or_default();
+        self.edges.push(Edge { from, to, attrs });
+    }
+
+    pub fn nodes(&self) -> impl Iterator<Item = (&Pubkey, /*stake:*/ &u64)> {
+        self.nodes.iter()
+    }
+
+    pub fn edges(&self) -> &[Edge] {
+        &self.edges
+    }
+
+    /// Returns (in-degree, out-degree) of each node, in the order of
+    /// Graph::nodes.
+    pub fn degrees(&self) -> Vec<(/*in:*/ usize, /*out:*/ usize)> {
+        let mut degrees = vec![(0, 0); self.nodes.len()];
+        for (from, to) in self.edge_indices() {
+            degrees[from].1 += 1;
+            degrees[to].0 += 1;
+        }
+        degrees
+    }
+
+    /// Returns strongly connected components, largest first.
+    pub fn strongly_connected_components(&self) -> Vec<Vec<Pubkey>> {
+        let num_nodes = self.nodes.len();
+        let adjacency = self.adjacency();
+        let mut reverse = vec![Vec::new(); num_nodes];
+        for (from, to) in self.edge_indices() {
+            reverse[to].push(from);
+        }
+        // Kosaraju's algorithm, with iterative depth-first search so that
+        // large graphs do not overflow the stack.
+        let mut order = Vec::with_capacity(num_nodes);
+        let mut visited = vec![false; num_nodes];
+        for root in 0..num_nodes {
+            if visited[root] {
+                continue;
+            }
+            visited[root] = true;
+            let mut stack = vec![(root, 0)];
+            while let Some((node, next)) = stack.last_mut() {
+                let node = *node;
+                match adjacency[node].get(*next) {
+                    None => {
+                        order.push(node);
+                        stack.pop();
+                    }
+                    Some(&other) => {
+                        *next += 1;
+                        if !visited[other] {
+                            visited[other] = true;
+                            stack.push((other, 0));
+                        }
+                    }
+                }
+            }
+        }
+        let mut components = Vec::new();
+        let mut visited = vec![false; num_nodes];
+        for &root in order.iter().rev() {
+            if visited[root] {
+                continue;
+            }
+            visited[root] = true;
+            let mut component = Vec::new();
+            let mut stack = vec![root];
+            while let Some(node) = stack.pop() {
+                component.push(*self.nodes.get_index(node).unwrap().0);
+                for &other in &reverse[node] {
+                    if !visited[other] {
+                        visited[other] = true;
+                        stack.push(other);
+                    }
+                }
+            }
+            components.push(component);
+        }
+        components.sort_by_key(|component| std::cmp::Reverse(component.len()));
+        components
+    }
+
+    /// Returns the longest shortest path (in number of hops) between any
+    /// two nodes, or None if some node is not reachable from another.
+    pub fn diameter(&self) -> Option<usize> {
+        let adjacency = self.adjacency();
+        let mut diameter = 0;
+        let mut dist = vec![usize::MAX; self.nodes.len()];
+        let mut queue = VecDeque::new();
+        for root in 0..self.nodes.len() {
+            dist.fill(usize::MAX);
+            dist[root] = 0;
+            queue.push_back(root);
+            let mut num_reached = 1;
+            while let Some(node) = queue.pop_front() {
+                diameter = diameter.max(dist[node]);
+                for &other in &adjacency[node] {
+                    if dist[other] == usize::MAX {
+                        dist[other] = dist[node] + 1;
+                        num_reached += 1;
+                        queue.push_back(other);
+                    }
+                }
+            }
+            if num_reached != self.nodes.len() {
+                return None;
+            }
+        }
+        Some(diameter)
+    }
+
+    /// Returns the minimum number of edges to remove so that no sink is
+    /// reachable from any of the sources, i.e. the max-flow with unit edge
+    /// capacities. Sources and sinks should be disjoint.
+    pub fn min_cut(&self, sources: &[Pubkey], sinks: &[Pubkey]) -> usize {
+        // Residual graph where node num_nodes is the super source and
+        // num_nodes + 1 is the super sink. Each edge is stored as
+        // (to, capacity, index of the reverse edge).
+        let num_nodes = self.nodes.len();
+        let (source, sink) = (num_nodes, num_nodes + 1);
+        let mut residual = vec![Vec::<(usize, usize, usize)>::new(); num_nodes + 2];
+        let mut add_edge = |from: usize, to: usize, capacity: usize| {
+            let (k, j) = (residual[to].len(), residual[from].len());
+            residual[from].push((to, capacity, k));
+            residual[to].push((from, 0, j));
+        };
+        for (from, to) in self.edge_indices() {
+            add_edge(from, to, 1);
+        }
+        for node in sources
+            .iter()
+            .filter_map(|node| self.nodes.get_index_of(node))
+        {
+            add_edge(source, node, usize::MAX);
+        }
+        for node in sinks
+            .iter()
+            .filter_map(|node| self.nodes.get_index_of(node))
+        {
+            add_edge(node, sink, usize::MAX);
+        }
+        // Edmonds-Karp; each augmenting path carries a unit of flow since
+        // the edges between source and sink have unit capacity.
+        let mut flow = 0;
+        loop {
+            let mut parent = vec![None; num_nodes + 2];
+            let mut queue = VecDeque::from([source]);
+            while let Some(node) = queue.pop_front() {
+                for (k, &(other, capacity, _)) in residual[node].iter().enumerate() {
+                    if capacity > 0 && other != source && parent[other].is_none() {
+                        parent[other] = Some((node, k));
+                        queue.push_back(other);
+                    }
+                }
+                if parent[sink].is_some() {
+                    break;
+                }
+            }
+            if parent[sink].is_none() {
+                return flow;
+            }
+            let mut node = sink;
+            while let Some((prev, k)) = parent[node] {
+                let (_, capacity, rev) = &mut residual[prev][k];
+                *capacity = capacity.saturating_sub(1);
+                let rev = *rev;
+                residual[node][rev].1 += 1;
+                node = prev;
+            }
+            flow += 1;
+        }
+    }
+
+    fn edge_indices(&self) -> impl Iterator<Item = (usize, usize)> + '_ {
+        self.edges.iter().map(|edge| {
+            let from = self.nodes.get_index_of(&edge.from).unwrap();
+            let to = self.nodes.get_index_of(&edge.to).unwrap();
+            (from, to)
+        })
+    }
+
+    fn adjacency(&self) -> Vec<Vec<usize>> {
+        let mut adjacency = vec![Vec::new(); self.nodes.len()];
+        for (from, to) in self.edge_indices() {
+            adjacency[from].push(to);
+        }
+        adjacency
+    }
+
+    /// Writes the graph to the file, in the format given by the file
+    /// extension: .dot or .gv for Graphviz and .graphml for GraphML.
+    pub fn write_to_file<P: AsRef<Path>>(&self, path: P) -> Result<(), Error> {
+        let path = path.as_ref();
+        let extension = path.extension().and_then(|ext| ext.to_str());
+        let mut writer = match extension {
+            Some("dot" | "gv" | "graphml") => BufWriter::new(File::create(path)?),
+            _ => return Err(Error::UnsupportedGraphFormat(path.display().to_string())),
+        };
+        if extension == Some("graphml") {
+            self.write_graphml(&mut writer)?;
+        } else {
+            self.write_dot(&mut writer)?;
+        }
+        writer.flush()?;
+        Ok(())
+    }
+
+    pub fn write_dot<W: Write>(&self, writer: &mut W) -> std::io::Result<()> {
+        writeln!(writer, "digraph {{")?;
+        for (pubkey, stake) in &self.nodes {
+            let label = &format!("{pubkey}")[..8];
+            writeln!(writer, "  \"{pubkey}\" [label=\"{label}\", stake={stake}];")?;
+        }
+        for Edge { from, to, attrs } in &self.edges {
+            let attrs = attrs
+                .iter()
+                .map(|(name, value)| format!("{name}=\"{}\"", value.replace('"', "\\\"")))
+                .collect::<Vec<_>>()
+                .join(", ");
+            writeln!(writer, "  \"{from}\" -> \"{to}\" [{attrs}];")?;
+        }
+        writeln!(writer, "}}")
+    }
+
+    pub fn write_graphml<W: Write>(&self, writer: &mut W) -> std::io::Result<()> {
+        writeln!(writer, r#"<?xml version="1.0" encoding="UTF-8"?>"#)?;
+        writeln!(
+            writer,
+            r#"<graphml xmlns="http://graphml.graphdrawing.org/xmlns">"#
+        )?;
+        writeln!(
+            writer,
+            r#"  <key id="stake" for="node" attr.name="stake" attr.type="long"/>"#
+        )?;
+        let names: IndexSet<&str> = self
+            .edges
+            .iter()
+            .flat_map(|edge| edge.attrs.iter().map(|(name, _)| *name))
+            .collect();
+        for name in &names {
+            writeln!(
+                writer,
+                r#"  <key id="{name}" for="edge" attr.name="{name}" attr.type="string"/>"#
+            )?;
+        }
+        writeln!(writer, r#"  <graph edgedefault="directed">"#)?;
+        for (pubkey, stake) in &self.nodes {
+            writeln!(
+                writer,
+                r#"    <node id="{pubkey}"><data key="stake">{stake}</data></node>"#
+            )?;
+        }
+        for Edge { from, to, attrs } in &self.edges {
+            write!(writer, r#"    <edge source="{from}" target="{to}">"#)?;
+            for (name, value) in attrs {
+                write!(writer, r#"<data key="{name}">{}</data>"#, escape_xml(value))?;
+            }
+            writeln!(writer, "</edge>")?;
+        }
+        writeln!(writer, "  </graph>")?;
+        writeln!(writer, "</graphml>")
+    }
+}
+
+fn escape_xml(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_graph_export() {
+        let nodes: Vec<_> = std::iter::repeat_with(Pubkey::new_unique).take(3).collect();
+        let mut graph = Graph::default();
+        graph.add_node(nodes[0], 42);
+        graph.add_edge(nodes[0], nodes[1], vec![("kind", String::from("first"))]);
+        graph.add_edge(nodes[1], nodes[2], vec![("kind", String::from("a<b"))]);
+        assert_eq!(graph.nodes().count(), 3);
+        assert_eq!(graph.edges().len(), 2);
+
+        let mut dot = Vec::new();
+        graph.write_dot(&mut dot).unwrap();
+        let dot = String::from_utf8(dot).unwrap();
+        assert!(dot.starts_with("digraph {\n"));
+        assert!(dot.contains(&format!("\"{}\" [label=", nodes[0])));
+        assert!(dot.contains("stake=42"));
+        assert!(dot.contains(&format!(
+            "\"{}\" -> \"{}\" [kind=\"first\"];",
+            nodes[0], nodes[1]
+        )));
+
+        let mut graphml = Vec::new();
+        graph.write_graphml(&mut graphml).unwrap();
+        let graphml = String::from_utf8(graphml).unwrap();
+        assert!(graphml.contains(&format!(
+            r#"<node id="{}"><data key="stake">42</data></node>"#,
+            nodes[0]
+        )));
+        assert!(graphml.contains(r#"<data key="kind">a&lt;b</data>"#));
+        assert_eq!(graphml.matches("<key id=\"kind\"").count(), 1);
+    }
+
+    #[test]
+    fn test_graph_metrics() {
+        let nodes: Vec<_> = std::iter::repeat_with(Pubkey::new_unique).take(6).collect();
+        let mut graph = Graph::default();
+        for node in &nodes {
+            graph.add_node(*node, 1);
+        }
+        // Two cycles {0, 1, 2} and {3, 4}, connected by 0 -> 3 and 1 -> 4,
+        // and node 5 which is only reachable from 4.
+        for (from, to) in [
+            (0, 1),
+            (1, 2),
+            (2, 0),
+            (3, 4),
+            (4, 3),
+            (0, 3),
+            (1, 4),
+            (4, 5),
+        ] {
+            graph.add_edge(nodes[from], nodes[to], Vec::default());
+        }
+        assert_eq!(
+            graph.degrees(),
+            [(1, 2), (1, 2), (1, 1), (2, 1), (2, 2), (1, 0)]
+        );
+        let mut components: Vec<Vec<usize>> = graph
+            .strongly_connected_components()
+            .into_iter()
+            .map(|component| {
+                let mut component: Vec<_> = component
+                    .iter()
+                    .map(|node| nodes.iter().position(|other| other == node).unwrap())
+                    .collect();
+                component.sort_unstable();
+                component
+            })
+            .collect();
+        components[1..].sort_unstable();
+        assert_eq!(components, [vec![0, 1, 2], vec![3, 4], vec![5]]);
+        assert_eq!(graph.diameter(), None);
+        assert_eq!(graph.min_cut(&nodes[..3], &nodes[3..5]), 2);
+        assert_eq!(graph.min_cut(&nodes[..1], &nodes[5..]), 1);
+        assert_eq!(graph.min_cut(&nodes[3..5], &nodes[..3]), 0);
+        graph.add_edge(nodes[5], nodes[2], Vec::default());
+        assert_eq!(graph.strongly_connected_components().len(), 1);
+        // 3 -> 4 -> 5 -> 2 -> 0 -> 1.
+        assert_eq!(graph.diameter(), Some(5));
+    }
+}
@@ -1,74 +1,124 @@
 use {
     crossbeam_channel::Sender,
     rand::Rng,
+    serde::{Deserialize, Serialize},
+    solana_bloom::bloom::BloomHashIndex,
     solana_client::client_error::ClientError,
-    solana_sdk::pubkey::{ParsePubkeyError, Pubkey},
-    std::{collections::HashMap, fmt::Debug},
+    solana_sdk::pubkey::ParsePubkeyError,
+    std::fmt::{self, Debug, Display, Formatter},
     thiserror::Error,
 };
 
 pub const API_MAINNET_BETA: &str = "https://api.mainnet-beta.solana.com";
 pub const API_TESTNET: &str = "https://api.testnet.solana.com";
 
+pub mod checkpoint;
+pub mod crds_value;
+pub mod freshness;
 pub mod gossip;
-mod push_active_set;
-mod received_cache;
+pub mod graph;
+pub mod push_active_set;
+pub mod received_cache;
+pub mod scheduler;
+pub mod simulation;
+pub mod stakes;
+pub mod weighted_sampling;
+
+/// Dense index of a node in the cluster. Hot paths use this instead of the
+/// node's pubkey, which is only needed at the cluster boundary and in output.
+#[derive(
+    Clone, Copy, Debug, Default, Deserialize, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize,
+)]
+pub struct NodeId(pub u32);
 
 #[derive(Debug, Error)]
 pub enum Error {
+    #[error(transparent)]
+    BincodeError(#[from] bincode::Error),
     #[error(transparent)]
     ClientError(#[from] ClientError),
+    #[error("invalid crds values: {0}")]
+    InvalidCrdsValues(String),
+    #[error("invalid simulation: {0}")]
+    InvalidSimulation(String),
+    #[error("invalid stakes: {0}")]
+    InvalidStakes(String),
+    #[error(transparent)]
+    IoError(#[from] std::io::Error),
+    #[error("LockErrorPoisoned")]
+    LockErrorPoisoned,
     #[error(transparent)]
     ParsePubkeyError(#[from] ParsePubkeyError),
     #[error(transparent)]
     RouterError(#[from] RouterError),
-    #[error("TryLockErrorPoisoned")]
-    TryLockErrorPoisoned,
+    #[error(transparent)]
+    ThreadPoolBuildError(#[from] rayon::ThreadPoolBuildError),
+    #[error("unsupported graph format: {0}")]
+    UnsupportedGraphFormat(String),
 }
 
 #[derive(Debug, Error)]
 pub enum RouterError {
-    #[error("duplicate pubkey: {0}")]
-    DuplicatePubkey(Pubkey),
     #[error("invalid packet drop rate: {0}")]
     InvalidPacketDropRate(f64),
     #[error("node not found: {0}")]
-    NodeNotFound(Pubkey),
+    NodeNotFound(NodeId),
     #[error("channel send error")]
     SendError,
 }
 
 pub struct Router<T> {
     packet_drop_rate: f64,
-    senders: HashMap<Pubkey, Sender<T>>,
+    // Indexed by NodeId.
+    senders: Vec<Sender<T>>,
+}
+
+impl NodeId {
+    pub fn index(self) -> usize {
+        self.0 as usize
+    }
+}
+
+impl From<usize> for NodeId {
+    fn from(index: usize) -> Self {
+        Self(u32::try_from(index).unwrap())
+    }
+}
+
+impl Display for NodeId {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl BloomHashIndex for NodeId {
+    fn hash_at_index(&self, hash_index: u64) -> u64 {
+        mix64(u64::from(self.0) ^ hash_index.wrapping_mul(0x9e37_79b9_7f4a_7c15))
+    }
 }
 
 impl<T> Router<T> {
-    pub fn new<I>(packet_drop_rate: f64, nodes: I) -> Result<Self, RouterError>
+    /// Senders are indexed by NodeId of the receiving node.
+    pub fn new<I>(packet_drop_rate: f64, senders: I) -> Result<Self, RouterError>
     where
-        I: IntoIterator<Item = (Pubkey, Sender<T>)>,
+        I: IntoIterator<Item = Sender<T>>,
     {
         if !(0.0..=1.0).contains(&packet_drop_rate) {
             return Err(RouterError::InvalidPacketDropRate(packet_drop_rate));
         }
-        let mut senders = HashMap::<Pubkey, Sender<T>>::new();
-        for (pubkey, sender) in nodes {
-            if senders.insert(pubkey, sender).is_some() {
-                return Err(RouterError::DuplicatePubkey(pubkey));
-            }
-        }
         Ok(Self {
             packet_drop_rate,
-            senders,
+            senders: senders.into_iter().collect(),
         })
     }
 }
 
 impl<T> Router<T> {
-    fn send<R: Rng>(&self, rng: &mut R, node: &Pubkey, data: T) -> Result<(), RouterError> {
+    /// Sends the data to the node, unless the packet is randomly dropped.
+    pub fn send<R: Rng>(&self, rng: &mut R, node: NodeId, data: T) -> Result<(), RouterError> {
         // TODO: How to simulate packets arriving with delay?
-        match self.senders.get(node) {
-            None => Err(RouterError::NodeNotFound(*node)),
+        match self.senders.get(node.index()) {
+            None => Err(RouterError::NodeNotFound(node)),
             Some(route) => {
                 if rng.gen_bool(self.packet_drop_rate) {
                     Ok(()) // Silently drop packet
@@ -78,6 +128,22 @@ impl<T> Router<T> {
             }
         }
     }
+
+    // Sends the data to the node, bypassing packet drops.
+    fn send_reliable(&self, node: NodeId, data: T) -> Result<(), RouterError> {
+        let route = self
+            .senders
+            .get(node.index())
+            .ok_or(RouterError::NodeNotFound(node))?;
+        route.send(data).map_err(|_| RouterError::SendError)
+    }
+}
+
+// splitmix64 finalizer; a cheap bijective hash of the input.
+pub(crate) fn mix64(mut hash: u64) -> u64 {
+    hash = (hash ^ (hash >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    hash = (hash ^ (hash >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    hash ^ (hash >> 31)
 }
 
 pub fn get_json_rpc_url(json_rpc_url: &str) -> &str {
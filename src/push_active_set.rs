@@ -1,25 +1,28 @@
 use {
+    crate::NodeId,
     indexmap::IndexMap,
     rand::Rng,
     solana_bloom::bloom::{AtomicBloom, Bloom},
     solana_gossip::weighted_shuffle::WeightedShuffle,
-    solana_sdk::{native_token::LAMPORTS_PER_SOL, pubkey::Pubkey},
-    std::collections::HashMap,
+    solana_sdk::native_token::LAMPORTS_PER_SOL,
+    std::iter::repeat_with,
 };
 
-const NUM_PUSH_ACTIVE_SET_ENTRIES: usize = 25;
+pub const NUM_PUSH_ACTIVE_SET_ENTRIES: usize = 25;
+// Number of nodes in each active-set entry as a multiple of the push fanout.
+pub const PUSH_ACTIVE_SET_FANOUT_MULTIPLE: usize = 3;
 
 // Each entry corresponds to a stake bucket for
 //     min stake of { this node, crds value owner }
 // The entry represents set of gossip nodes to actively
 // push to for crds values belonging to the bucket.
 #[derive(Default)]
-pub(crate) struct PushActiveSet([PushActiveSetEntry; NUM_PUSH_ACTIVE_SET_ENTRIES]);
+pub struct PushActiveSet([PushActiveSetEntry; NUM_PUSH_ACTIVE_SET_ENTRIES]);
 
 // Keys are gossip nodes to push messages to.
 // Values are which origins the node has pruned.
 #[derive(Default)]
-struct PushActiveSetEntry(IndexMap</*node:*/ Pubkey, /*origins:*/ AtomicBloom<Pubkey>>);
+struct PushActiveSetEntry(IndexMap</*node:*/ NodeId, /*origins:*/ AtomicBloom<NodeId>>);
 
 impl PushActiveSet {
     #[cfg(debug_assertions)]
@@ -27,45 +30,50 @@ impl PushActiveSet {
     #[cfg(not(debug_assertions))]
     const MIN_NUM_BLOOM_ITEMS: usize = crate::gossip::CRDS_UNIQUE_PUBKEY_CAPACITY;
 
-    pub(crate) fn get_nodes<'a>(
+    pub fn get_nodes<'a>(
         &'a self,
-        pubkey: &Pubkey,    // This node.
-        origin: &'a Pubkey, // CRDS value owner.
+        node: NodeId,   // This node.
+        origin: NodeId, // CRDS value owner.
         // If true forces gossip push even if the node has pruned the origin.
-        should_force_push: impl FnMut(&Pubkey) -> bool + 'a,
-        stakes: &HashMap<Pubkey, u64>,
-    ) -> impl Iterator<Item = &Pubkey> + 'a {
-        let stake = stakes.get(pubkey).min(stakes.get(origin));
+        should_force_push: impl FnMut(NodeId) -> bool + 'a,
+        stakes: &[u64], // Indexed by NodeId.
+    ) -> impl Iterator<Item = NodeId> + 'a {
+        let stake = stakes[node.index()].min(stakes[origin.index()]);
         self.get_entry(stake).get_nodes(origin, should_force_push)
     }
 
-    // Prunes origins for the given gossip node.
-    // We will stop pushing messages from the specified origins to the node.
-    pub(crate) fn prune(
+    /// Prunes origins for the given gossip node.
+    /// We will stop pushing messages from the specified origins to the node.
+    pub fn prune(
         &self,
-        pubkey: &Pubkey,    // This node.
-        node: &Pubkey,      // Gossip node.
-        origins: &[Pubkey], // CRDS value owners.
-        stakes: &HashMap<Pubkey, u64>,
+        node: NodeId,       // This node.
+        peer: NodeId,       // Gossip node.
+        origins: &[NodeId], // CRDS value owners.
+        stakes: &[u64],     // Indexed by NodeId.
     ) {
-        let stake = stakes.get(pubkey);
-        for origin in origins {
-            if origin == pubkey {
+        let stake = stakes[node.index()];
+        for &origin in origins {
+            if origin == node {
                 continue;
             }
-            let stake = stake.min(stakes.get(origin));
-            self.get_entry(stake).prune(node, origin)
+            let stake = stake.min(stakes[origin.index()]);
+            self.get_entry(stake).prune(peer, origin)
         }
     }
 
-    pub(crate) fn rotate<R: Rng>(
+    /// Returns true if the node is in any of the active-set entries.
+    pub fn contains(&self, node: NodeId) -> bool {
+        self.0.iter().any(|entry| entry.0.contains_key(&node))
+    }
+
+    pub fn rotate<R: Rng>(
         &mut self,
         rng: &mut R,
         size: usize, // Number of nodes to retain in each active-set entry.
         cluster_size: usize,
         // Gossip nodes to be sampled for each push active set.
-        nodes: &[Pubkey],
-        stakes: &HashMap<Pubkey, u64>,
+        nodes: &[NodeId],
+        stakes: &[u64], // Indexed by NodeId.
     ) {
         let num_bloom_filter_items = cluster_size.max(Self::MIN_NUM_BLOOM_ITEMS);
         // Active set of nodes to push to are sampled from these gossip nodes,
@@ -73,7 +81,7 @@ impl PushActiveSet {
         // node.
         let buckets: Vec<_> = nodes
             .iter()
-            .map(|node| get_stake_bucket(stakes.get(node)))
+            .map(|node| get_stake_bucket(Some(&stakes[node.index()])))
             .collect();
         // (k, entry) represents push active set where the stake bucket of
         //     min stake of {this node, crds value owner}
@@ -82,25 +90,46 @@ impl PushActiveSet {
         for (k, entry) in self.0.iter_mut().enumerate() {
             let weights: Vec<u64> = buckets
                 .iter()
-                .map(|&bucket| {
-                    // bucket <- get_stake_bucket(min stake of {
-                    //  this node, crds value owner and gossip peer
-                    // })
-                    // weight <- (bucket + 1)^2
-                    // min stake of {...} is a proxy for how much we care about
-                    // the link, and tries to mirror similar logic on the
-                    // receiving end when pruning incoming links:
-                    // https://github.com/solana-labs/solana/blob/81394cf92/gossip/src/received_cache.rs#L100-L105
-                    let bucket = bucket.min(k) as u64;
-                    bucket.saturating_add(1).saturating_pow(2)
-                })
+                .map(|&bucket| get_sampling_weight(k, bucket))
                 .collect();
             entry.rotate(rng, size, num_bloom_filter_items, nodes, &weights);
         }
     }
 
-    fn get_entry(&self, stake: Option<&u64>) -> &PushActiveSetEntry {
-        &self.0[get_stake_bucket(stake)]
+    // Returns the nodes in each active-set entry along with the origins they
+    // have pruned. AtomicBloom converts to Bloom only by value, so entries
+    // are taken out and rebuilt from copies of the bloom filters.
+    pub(crate) fn checkpoint(&mut self) -> Vec<Vec<(NodeId, Bloom<NodeId>)>> {
+        self.0
+            .iter_mut()
+            .map(|entry| {
+                let nodes: Vec<(NodeId, Bloom<NodeId>)> = std::mem::take(&mut entry.0)
+                    .into_iter()
+                    .map(|(node, bloom)| (node, Bloom::from(bloom)))
+                    .collect();
+                entry.0 = nodes
+                    .iter()
+                    .map(|(node, bloom)| (*node, AtomicBloom::from(bloom.clone())))
+                    .collect();
+                nodes
+            })
+            .collect()
+    }
+
+    pub(crate) fn from_checkpoint(entries: Vec<Vec<(NodeId, Bloom<NodeId>)>>) -> Self {
+        assert_eq!(entries.len(), NUM_PUSH_ACTIVE_SET_ENTRIES);
+        let mut active_set = Self::default();
+        for (entry, nodes) in active_set.0.iter_mut().zip(entries) {
+            entry.0 = nodes
+                .into_iter()
+                .map(|(node, bloom)| (node, AtomicBloom::from(bloom)))
+                .collect();
+        }
+        active_set
+    }
+
+    fn get_entry(&self, stake: u64) -> &PushActiveSetEntry {
+        &self.0[get_stake_bucket(Some(&stake))]
     }
 }
 
@@ -110,25 +139,25 @@ impl PushActiveSetEntry {
 
     fn get_nodes<'a>(
         &'a self,
-        origin: &'a Pubkey,
+        origin: NodeId,
         // If true forces gossip push even if the node has pruned the origin.
-        mut should_force_push: impl FnMut(&Pubkey) -> bool + 'a,
-    ) -> impl Iterator<Item = &Pubkey> + 'a {
+        mut should_force_push: impl FnMut(NodeId) -> bool + 'a,
+    ) -> impl Iterator<Item = NodeId> + 'a {
         self.0
             .iter()
-            .filter(move |(node, bloom_filter)| {
-                !bloom_filter.contains(origin) || should_force_push(node)
+            .filter(move |(&node, bloom_filter)| {
+                !bloom_filter.contains(&origin) || should_force_push(node)
             })
-            .map(|(node, _bloom_filter)| node)
+            .map(|(&node, _bloom_filter)| node)
     }
 
     fn prune(
         &self,
-        node: &Pubkey,   // Gossip node.
-        origin: &Pubkey, // CRDS value owner
+        node: NodeId,   // Gossip node.
+        origin: NodeId, // CRDS value owner
     ) {
-        if let Some(bloom_filter) = self.0.get(node) {
-            bloom_filter.add(origin);
+        if let Some(bloom_filter) = self.0.get(&node) {
+            bloom_filter.add(&origin);
         }
     }
 
@@ -137,27 +166,34 @@ impl PushActiveSetEntry {
         rng: &mut R,
         size: usize, // Number of nodes to retain.
         num_bloom_filter_items: usize,
-        nodes: &[Pubkey],
+        nodes: &[NodeId],
         weights: &[u64],
     ) {
         debug_assert_eq!(nodes.len(), weights.len());
         debug_assert!(weights.iter().all(|&weight| weight != 0u64));
+        // The shuffle borrows rng, so new nodes are collected first and
+        // their bloom filters keyed from rng afterwards.
+        let mut new_nodes = Vec::new();
         let shuffle = WeightedShuffle::new("rotate-active-set", weights).shuffle(rng);
         for node in shuffle.map(|k| &nodes[k]) {
             // We intend to discard the oldest/first entry in the index-map.
-            if self.0.len() > size {
+            if self.0.len() + new_nodes.len() > size {
                 break;
             }
-            if self.0.contains_key(node) {
+            if self.0.contains_key(node) || new_nodes.contains(node) {
                 continue;
             }
-            let bloom = AtomicBloom::from(Bloom::random(
+            new_nodes.push(*node);
+        }
+        for node in new_nodes {
+            let bloom = AtomicBloom::from(make_bloom(
+                rng,
                 num_bloom_filter_items,
                 Self::BLOOM_FALSE_RATE,
                 Self::BLOOM_MAX_BITS,
             ));
-            bloom.add(node);
-            self.0.insert(*node, bloom);
+            bloom.add(&node);
+            self.0.insert(node, bloom);
         }
         // Drop the oldest entry while preserving the ordering of others.
         while self.0.len() > size {
@@ -166,8 +202,45 @@ impl PushActiveSetEntry {
     }
 }
 
-// Maps stake to bucket index.
-fn get_stake_bucket(stake: Option<&u64>) -> usize {
+// Same as Bloom::random, except that keys are drawn from the given rng
+// instead of thread_rng, so that seeded runs are reproducible.
+fn make_bloom<R: Rng>(
+    rng: &mut R,
+    num_items: usize,
+    false_rate: f64,
+    max_bits: usize,
+) -> Bloom<NodeId> {
+    let num_items = num_items as f64;
+    let num_bits = (num_items * false_rate.ln() / (1.0 / 2f64.powf(2f64.ln())).ln()).ceil();
+    let num_bits = (num_bits as usize).min(max_bits).max(1);
+    let num_keys = if num_items == 0.0 {
+        0
+    } else {
+        (num_bits as f64 / num_items * 2f64.ln()).round().max(1.0) as usize
+    };
+    let keys = repeat_with(|| rng.gen()).take(num_keys).collect();
+    Bloom::new(num_bits, keys)
+}
+
+/// Returns the weight with which a gossip peer in the given stake bucket is
+/// sampled into the active-set entry `k`, i.e. the entry for crds values
+/// where the stake bucket of min stake of {this node, crds value owner} is
+/// equal to `k`.
+pub fn get_sampling_weight(k: usize, bucket: usize) -> u64 {
+    // bucket <- get_stake_bucket(min stake of {
+    //  this node, crds value owner and gossip peer
+    // })
+    // weight <- (bucket + 1)^2
+    // min stake of {...} is a proxy for how much we care about
+    // the link, and tries to mirror similar logic on the
+    // receiving end when pruning incoming links:
+    // https://github.com/solana-labs/solana/blob/81394cf92/gossip/src/received_cache.rs#L100-L105
+    let bucket = bucket.min(k) as u64;
+    bucket.saturating_add(1).saturating_pow(2)
+}
+
+/// Maps stake to bucket index.
+pub fn get_stake_bucket(stake: Option<&u64>) -> usize {
     let stake = stake.copied().unwrap_or_default() / LAMPORTS_PER_SOL;
     let bucket = u64::BITS - stake.leading_zeros();
     (bucket as usize).min(NUM_PUSH_ACTIVE_SET_ENTRIES - 1)
@@ -175,7 +248,16 @@ fn get_stake_bucket(stake: Option<&u64>) -> usize {
 
 #[cfg(test)]
 mod tests {
-    use {super::*, rand::SeedableRng, rand_chacha::ChaChaRng, std::iter::repeat_with};
+    use {
+        super::*,
+        proptest::{
+            collection::vec, prop_assert, prop_assert_eq, proptest,
+            test_runner::Config as ProptestConfig,
+        },
+        rand::SeedableRng,
+        rand_chacha::ChaChaRng,
+        std::collections::HashSet,
+    };
 
     #[test]
     fn test_get_stake_bucket() {
@@ -205,11 +287,11 @@ mod tests {
         const CLUSTER_SIZE: usize = 117;
         const MAX_STAKE: u64 = (1 << 20) * LAMPORTS_PER_SOL;
         let mut rng = ChaChaRng::from_seed([189u8; 32]);
-        let pubkey = Pubkey::new_unique();
-        let nodes: Vec<_> = repeat_with(Pubkey::new_unique).take(20).collect();
-        let stakes = repeat_with(|| rng.gen_range(1, MAX_STAKE));
-        let mut stakes: HashMap<_, _> = nodes.iter().copied().zip(stakes).collect();
-        stakes.insert(pubkey, rng.gen_range(1, MAX_STAKE));
+        let node = NodeId(20);
+        let nodes: Vec<_> = (0..20).map(NodeId).collect();
+        let stakes: Vec<_> = repeat_with(|| rng.gen_range(1, MAX_STAKE))
+            .take(21)
+            .collect();
         let mut active_set = PushActiveSet::default();
         assert!(active_set.0.iter().all(|entry| entry.0.is_empty()));
         active_set.rotate(&mut rng, 5, CLUSTER_SIZE, &nodes, &stakes);
@@ -220,48 +302,58 @@ mod tests {
                 assert!(filter.contains(node));
             }
         }
-        let other = &nodes[5];
-        let origin = &nodes[17];
+        let other = nodes[5];
+        let origin = nodes[17];
         assert!(active_set
-            .get_nodes(&pubkey, origin, |_| false, &stakes)
-            .eq([13, 5, 18, 16, 0].into_iter().map(|k| &nodes[k])));
+            .get_nodes(node, origin, |_| false, &stakes)
+            .eq([5, 18, 8, 14].into_iter().map(|k| nodes[k])));
         assert!(active_set
-            .get_nodes(&pubkey, other, |_| false, &stakes)
-            .eq([13, 18, 16, 0].into_iter().map(|k| &nodes[k])));
-        active_set.prune(&pubkey, &nodes[5], &[*origin], &stakes);
-        active_set.prune(&pubkey, &nodes[3], &[*origin], &stakes);
-        active_set.prune(&pubkey, &nodes[16], &[*origin], &stakes);
+            .get_nodes(node, other, |_| false, &stakes)
+            .eq([17, 18, 8, 14].into_iter().map(|k| nodes[k])));
+        active_set.prune(node, nodes[5], &[origin], &stakes);
+        active_set.prune(node, nodes[3], &[origin], &stakes);
+        active_set.prune(node, nodes[8], &[origin], &stakes);
         assert!(active_set
-            .get_nodes(&pubkey, origin, |_| false, &stakes)
-            .eq([13, 18, 0].into_iter().map(|k| &nodes[k])));
+            .get_nodes(node, origin, |_| false, &stakes)
+            .eq([18, 14].into_iter().map(|k| nodes[k])));
         assert!(active_set
-            .get_nodes(&pubkey, other, |_| false, &stakes)
-            .eq([13, 18, 16, 0].into_iter().map(|k| &nodes[k])));
+            .get_nodes(node, other, |_| false, &stakes)
+            .eq([17, 18, 8, 14].into_iter().map(|k| nodes[k])));
         active_set.rotate(&mut rng, 7, CLUSTER_SIZE, &nodes, &stakes);
         assert!(active_set.0.iter().all(|entry| entry.0.len() == 7));
         assert!(active_set
-            .get_nodes(&pubkey, origin, |_| false, &stakes)
-            .eq([18, 0, 7, 15, 11].into_iter().map(|k| &nodes[k])));
+            .get_nodes(node, origin, |_| false, &stakes)
+            .eq([18, 14, 4, 0, 13].into_iter().map(|k| nodes[k])));
         assert!(active_set
-            .get_nodes(&pubkey, other, |_| false, &stakes)
-            .eq([18, 16, 0, 7, 15, 11].into_iter().map(|k| &nodes[k])));
-        let origins = [*origin, *other];
-        active_set.prune(&pubkey, &nodes[18], &origins, &stakes);
-        active_set.prune(&pubkey, &nodes[0], &origins, &stakes);
-        active_set.prune(&pubkey, &nodes[15], &origins, &stakes);
+            .get_nodes(node, other, |_| false, &stakes)
+            .eq([18, 8, 14, 4, 0, 13].into_iter().map(|k| nodes[k])));
+        let origins = [origin, other];
+        active_set.prune(node, nodes[18], &origins, &stakes);
+        active_set.prune(node, nodes[0], &origins, &stakes);
+        active_set.prune(node, nodes[13], &origins, &stakes);
         assert!(active_set
-            .get_nodes(&pubkey, origin, |_| false, &stakes)
-            .eq([7, 11].into_iter().map(|k| &nodes[k])));
+            .get_nodes(node, origin, |_| false, &stakes)
+            .eq([14, 4].into_iter().map(|k| nodes[k])));
         assert!(active_set
-            .get_nodes(&pubkey, other, |_| false, &stakes)
-            .eq([16, 7, 11].into_iter().map(|k| &nodes[k])));
+            .get_nodes(node, other, |_| false, &stakes)
+            .eq([8, 14, 4].into_iter().map(|k| nodes[k])));
+        // Pruned origins are retained across checkpoints.
+        let checkpoint = active_set.checkpoint();
+        for active_set in [&active_set, &PushActiveSet::from_checkpoint(checkpoint)] {
+            assert!(active_set
+                .get_nodes(node, origin, |_| false, &stakes)
+                .eq([14, 4].into_iter().map(|k| nodes[k])));
+            assert!(active_set
+                .get_nodes(node, other, |_| false, &stakes)
+                .eq([8, 14, 4].into_iter().map(|k| nodes[k])));
+        }
     }
 
     #[test]
     fn test_push_active_set_entry() {
         const NUM_BLOOM_FILTER_ITEMS: usize = 100;
         let mut rng = ChaChaRng::from_seed([147u8; 32]);
-        let nodes: Vec<_> = repeat_with(Pubkey::new_unique).take(20).collect();
+        let nodes: Vec<_> = (0..20).map(NodeId).collect();
         let weights: Vec<_> = repeat_with(|| rng.gen_range(1, 1000)).take(20).collect();
         let mut entry = PushActiveSetEntry::default();
         entry.rotate(
@@ -272,9 +364,9 @@ mod tests {
             &weights,
         );
         assert_eq!(entry.0.len(), 5);
-        let keys = [&nodes[16], &nodes[11], &nodes[17], &nodes[14], &nodes[5]];
-        assert!(entry.0.keys().eq(keys));
-        for origin in &nodes {
+        let keys = [nodes[16], nodes[11], nodes[17], nodes[14], nodes[5]];
+        assert!(entry.0.keys().copied().eq(keys));
+        for &origin in &nodes {
             if !keys.contains(&origin) {
                 assert!(entry.get_nodes(origin, |_| false).eq(keys));
             } else {
@@ -295,25 +387,106 @@ mod tests {
                 .eq(keys.into_iter().filter(|&node| node != origin)));
         }
         // Assert that prune excludes node from get.
-        let origin = &nodes[3];
-        entry.prune(&nodes[11], origin);
-        entry.prune(&nodes[14], origin);
-        entry.prune(&nodes[19], origin);
+        let origin = nodes[3];
+        entry.prune(nodes[11], origin);
+        entry.prune(nodes[14], origin);
+        entry.prune(nodes[19], origin);
         assert!(entry.get_nodes(origin, |_| true).eq(keys));
         assert!(entry.get_nodes(origin, |_| false).eq(keys
             .into_iter()
-            .filter(|&&node| node != nodes[11] && node != nodes[14])));
+            .filter(|&node| node != nodes[11] && node != nodes[14])));
         // Assert that rotate adds new nodes.
         entry.rotate(&mut rng, 5, NUM_BLOOM_FILTER_ITEMS, &nodes, &weights);
-        let keys = [&nodes[11], &nodes[17], &nodes[14], &nodes[5], &nodes[7]];
-        assert!(entry.0.keys().eq(keys));
+        let keys = [nodes[11], nodes[17], nodes[14], nodes[5], nodes[18]];
+        assert!(entry.0.keys().copied().eq(keys));
         entry.rotate(&mut rng, 6, NUM_BLOOM_FILTER_ITEMS, &nodes, &weights);
         let keys = [
-            &nodes[17], &nodes[14], &nodes[5], &nodes[7], &nodes[1], &nodes[13],
+            nodes[17], nodes[14], nodes[5], nodes[18], nodes[1], nodes[6],
         ];
-        assert!(entry.0.keys().eq(keys));
+        assert!(entry.0.keys().copied().eq(keys));
         entry.rotate(&mut rng, 4, NUM_BLOOM_FILTER_ITEMS, &nodes, &weights);
-        let keys = [&nodes[5], &nodes[7], &nodes[1], &nodes[13]];
-        assert!(entry.0.keys().eq(keys));
+        let keys = [nodes[5], nodes[18], nodes[1], nodes[6]];
+        assert!(entry.0.keys().copied().eq(keys));
+    }
+
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(64))]
+
+        #[test]
+        fn prop_push_active_set_entry(
+            seed: u64,
+            weights in vec(1u64..1_000, 1..40),
+            // Entry size and pruned (node, origin) pairs after each rotation.
+            rounds in vec((0usize..16, vec((0usize..40, 0usize..40), 0..8)), 1..8),
+        ) {
+            const NUM_BLOOM_FILTER_ITEMS: usize = 100;
+            let mut rng = ChaChaRng::seed_from_u64(seed);
+            let nodes: Vec<_> = (0..weights.len()).map(NodeId::from).collect();
+            let mut entry = PushActiveSetEntry::default();
+            for (size, prunes) in rounds {
+                let keys: Vec<NodeId> = entry.0.keys().copied().collect();
+                entry.rotate(&mut rng, size, NUM_BLOOM_FILTER_ITEMS, &nodes, &weights);
+                prop_assert_eq!(entry.0.len(), size.min(nodes.len()));
+                // Retained nodes keep their relative order.
+                let retained: Vec<NodeId> = keys
+                    .into_iter()
+                    .filter(|node| entry.0.contains_key(node))
+                    .collect();
+                prop_assert!(entry.0.keys().take(retained.len()).eq(&retained));
+                for &(node, origin) in &prunes {
+                    entry.prune(NodeId::from(node), NodeId::from(origin));
+                }
+                for &origin in &nodes {
+                    prop_assert!(entry.get_nodes(origin, |_| true).eq(entry.0.keys().copied()));
+                    let pushed: HashSet<NodeId> = entry.get_nodes(origin, |_| false).collect();
+                    // A node is always pruned for itself.
+                    prop_assert!(!pushed.contains(&origin));
+                    for &(node, other) in &prunes {
+                        let node = NodeId::from(node);
+                        prop_assert!(NodeId::from(other) != origin || !pushed.contains(&node));
+                    }
+                }
+            }
+        }
+
+        #[test]
+        fn prop_push_active_set(
+            seed: u64,
+            stakes in vec(0u64..(1 << 30) * LAMPORTS_PER_SOL, 2..40),
+            size in 0usize..12,
+            // Pruned (peer, origin) pairs.
+            prunes in vec((0usize..40, 0usize..40), 0..16),
+        ) {
+            let mut rng = ChaChaRng::seed_from_u64(seed);
+            let node = NodeId::from(stakes.len() - 1);
+            let nodes: Vec<_> = (0..stakes.len() - 1).map(NodeId::from).collect();
+            let mut active_set = PushActiveSet::default();
+            active_set.rotate(&mut rng, size, stakes.len(), &nodes, &stakes);
+            for entry in &active_set.0 {
+                prop_assert_eq!(entry.0.len(), size.min(nodes.len()));
+            }
+            prop_assert!(!active_set.contains(node));
+            let get_nodes = |active_set: &PushActiveSet, origin| -> Vec<NodeId> {
+                active_set.get_nodes(node, origin, |_| false, &stakes).collect()
+            };
+            let pushed = get_nodes(&active_set, node);
+            let prunes: Vec<_> = prunes
+                .into_iter()
+                .map(|(peer, origin)| {
+                    let peer = NodeId::from(peer % stakes.len());
+                    (peer, NodeId::from(origin % stakes.len()))
+                })
+                .collect();
+            for &(peer, origin) in &prunes {
+                active_set.prune(node, peer, &[origin], &stakes);
+            }
+            // Values of this node are never pruned.
+            prop_assert_eq!(get_nodes(&active_set, node), pushed);
+            for &(peer, origin) in &prunes {
+                if origin != node {
+                    prop_assert!(!get_nodes(&active_set, origin).contains(&peer));
+                }
+            }
+        }
     }
 }
@@ -1,87 +1,245 @@
 use {
+    crate::NodeId,
     itertools::Itertools,
+    log::debug,
     lru::LruCache,
-    solana_sdk::pubkey::Pubkey,
-    std::{cmp::Reverse, collections::HashMap},
+    serde::{Deserialize, Deserializer, Serialize, Serializer},
+    std::{
+        cmp::Reverse,
+        collections::HashMap,
+        fmt::{self, Display, Formatter},
+        time::Duration,
+    },
 };
 
-// For each origin, tracks which nodes have sent messages from that origin and
-// their respective score in terms of timeliness of delivered messages.
-pub(crate) struct ReceivedCache(LruCache</*origin/owner:*/ Pubkey, ReceivedCacheEntry>);
+// Log target for prune decisions, e.g. RUST_LOG=INFO,prune_audit=debug
+const PRUNE_AUDIT_LOG_TARGET: &str = "prune_audit";
 
-#[derive(Clone, Default)]
+/// For each origin, tracks which nodes have sent messages from that origin and
+/// their respective score in terms of timeliness of delivered messages.
+pub struct ReceivedCache {
+    cache: LruCache</*origin/owner:*/ NodeId, ReceivedCacheEntry>,
+    // Minimum number of upserts before a cache entry can be pruned.
+    min_num_upserts: usize,
+    // Limit how big each cache entry can get if it is spammed
+    // with old messages with random pubkeys.
+    entry_capacity: usize,
+    // Threshold for the number of duplicates before which a message
+    // is counted as timely towards node's score.
+    num_dups_threshold: usize,
+    scorer: ReceivedCacheScorer,
+}
+
+// Serialized form of ReceivedCache, with cache entries ordered from least to
+// most recently used.
+#[derive(Deserialize, Serialize)]
+struct ReceivedCacheRepr<E> {
+    capacity: usize,
+    entries: Vec<(/*origin/owner:*/ NodeId, E)>,
+    min_num_upserts: usize,
+    entry_capacity: usize,
+    num_dups_threshold: usize,
+    scorer: ReceivedCacheScorer,
+}
+
+/// Determines how much a delivered message adds to the sender's score.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub enum ReceivedCacheScorer {
+    /// Scores 1 if the message is among the first `num_dups_threshold`
+    /// copies delivered.
+    NumDups,
+    /// Scores `num_dups_threshold - num_dups`, so that earlier copies
+    /// weigh more than later ones.
+    ArrivalRank,
+    /// Scores proportional to how early the message arrived relative to
+    /// the first copy, decaying linearly to zero at `max_delay`.
+    ArrivalDelay { max_delay: Duration },
+}
+
+/// Outcome of pruning a received-cache entry, i.e. why each node which
+/// has delivered messages from the origin was kept or pruned.
+#[derive(Clone, Debug)]
+pub struct PruneDecision {
+    pub node: NodeId,   // This node.
+    pub origin: NodeId, // CRDS value owner.
+    pub min_ingress_nodes: usize,
+    pub min_ingress_stake: u64,
+    // Sorted by (score, stake) in descending order.
+    pub candidates: Vec<PruneCandidate>,
+}
+
+#[derive(Clone, Debug)]
+pub struct PruneCandidate {
+    pub node: NodeId,
+    pub score: usize,
+    pub stake: u64,
+    // Cumulative ingress stake of the candidates ranked before this node.
+    pub ingress_stake: u64,
+    pub pruned: bool,
+}
+
+#[derive(Clone, Default, Deserialize, Eq, PartialEq, Serialize)]
 struct ReceivedCacheEntry {
-    nodes: HashMap<Pubkey, /*score:*/ usize>,
+    nodes: HashMap<NodeId, /*score:*/ usize>,
     num_upserts: usize,
 }
 
 impl ReceivedCache {
-    // Minimum number of upserts before a cache entry can be pruned.
-    const MIN_NUM_UPSERTS: usize = 20;
-
-    pub(crate) fn new(capacity: usize) -> Self {
-        Self(LruCache::new(capacity))
+    pub fn new(
+        capacity: usize,
+        min_num_upserts: usize,
+        entry_capacity: usize,
+        num_dups_threshold: usize,
+        scorer: ReceivedCacheScorer,
+    ) -> Self {
+        Self {
+            cache: LruCache::new(capacity),
+            min_num_upserts,
+            entry_capacity,
+            num_dups_threshold,
+            scorer,
+        }
     }
 
-    pub(crate) fn record(&mut self, origin: Pubkey, node: Pubkey, num_dups: usize) {
-        match self.0.get_mut(&origin) {
-            Some(entry) => entry.record(node, num_dups),
+    pub fn record(
+        &mut self,
+        origin: NodeId,
+        node: NodeId,
+        num_dups: usize,
+        delay: Duration, // Since the first delivery of the message.
+    ) {
+        let score = self.scorer.score(num_dups, delay, self.num_dups_threshold);
+        let entry_capacity = self.entry_capacity;
+        match self.cache.get_mut(&origin) {
+            Some(entry) => entry.record(node, num_dups, score, entry_capacity),
             None => {
                 let mut entry = ReceivedCacheEntry::default();
-                entry.record(node, num_dups);
-                self.0.put(origin, entry);
+                entry.record(node, num_dups, score, entry_capacity);
+                self.cache.put(origin, entry);
             }
         }
     }
 
-    pub(crate) fn prune(
+    /// Returns None if the entry is missing or does not have enough upserts
+    /// yet to be pruned.
+    pub fn prune(
         &mut self,
-        pubkey: &Pubkey, // This node.
-        origin: Pubkey,  // CRDS value owner.
+        node: NodeId,   // This node.
+        origin: NodeId, // CRDS value owner.
         stake_threshold: f64,
         min_ingress_nodes: usize,
-        stakes: &HashMap<Pubkey, u64>,
-    ) -> impl Iterator<Item = Pubkey> {
-        match self.0.peek_mut(&origin) {
+        stakes: &[u64], // Indexed by NodeId.
+    ) -> Option<PruneDecision> {
+        match self.cache.peek_mut(&origin) {
             None => None,
-            Some(entry) if entry.num_upserts < Self::MIN_NUM_UPSERTS => None,
-            Some(entry) => Some(
-                std::mem::take(entry)
-                    .prune(pubkey, &origin, stake_threshold, min_ingress_nodes, stakes)
-                    .filter(move |node| node != &origin),
-            ),
+            Some(entry) if entry.num_upserts < self.min_num_upserts => None,
+            Some(entry) => {
+                debug_assert!(entry.num_upserts >= self.min_num_upserts);
+                let decision = std::mem::take(entry).prune(
+                    node,
+                    origin,
+                    stake_threshold,
+                    min_ingress_nodes,
+                    stakes,
+                );
+                debug!(target: PRUNE_AUDIT_LOG_TARGET, "{decision}");
+                Some(decision)
+            }
         }
-        .into_iter()
-        .flatten()
     }
+}
 
-    #[cfg(test)]
-    fn mock_clone(&self) -> Self {
-        let mut cache = LruCache::new(self.0.cap());
-        for (&origin, entry) in self.0.iter().rev() {
+impl Clone for ReceivedCache {
+    fn clone(&self) -> Self {
+        let mut cache = LruCache::new(self.cache.cap());
+        for (&origin, entry) in self.cache.iter().rev() {
             cache.put(origin, entry.clone());
         }
-        Self(cache)
+        Self { cache, ..*self }
     }
 }
 
-impl ReceivedCacheEntry {
-    // Limit how big the cache can get if it is spammed
-    // with old messages with random pubkeys.
-    const CAPACITY: usize = 50;
-    // Threshold for the number of duplicates before which a message
-    // is counted as timely towards node's score.
-    const NUM_DUPS_THRESHOLD: usize = 2;
+// Compares entries in order of recent use, along with the parameters.
+impl PartialEq for ReceivedCache {
+    fn eq(&self, other: &Self) -> bool {
+        self.cache.cap() == other.cache.cap()
+            && self.cache.iter().eq(other.cache.iter())
+            && self.min_num_upserts == other.min_num_upserts
+            && self.entry_capacity == other.entry_capacity
+            && self.num_dups_threshold == other.num_dups_threshold
+            && self.scorer == other.scorer
+    }
+}
+
+impl Serialize for ReceivedCache {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        ReceivedCacheRepr {
+            capacity: self.cache.cap(),
+            entries: self
+                .cache
+                .iter()
+                .rev()
+                .map(|(&origin, entry)| (origin, entry))
+                .collect(),
+            min_num_upserts: self.min_num_upserts,
+            entry_capacity: self.entry_capacity,
+            num_dups_threshold: self.num_dups_threshold,
+            scorer: self.scorer,
+        }
+        .serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for ReceivedCache {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let repr = ReceivedCacheRepr::<ReceivedCacheEntry>::deserialize(deserializer)?;
+        let mut cache = LruCache::new(repr.capacity);
+        for (origin, entry) in repr.entries {
+            cache.put(origin, entry);
+        }
+        Ok(Self {
+            cache,
+            min_num_upserts: repr.min_num_upserts,
+            entry_capacity: repr.entry_capacity,
+            num_dups_threshold: repr.num_dups_threshold,
+            scorer: repr.scorer,
+        })
+    }
+}
 
-    fn record(&mut self, node: Pubkey, num_dups: usize) {
+impl ReceivedCacheScorer {
+    // Number of points awarded to the first delivery under ArrivalDelay.
+    const MAX_DELAY_SCORE: u128 = 10;
+
+    fn score(&self, num_dups: usize, delay: Duration, num_dups_threshold: usize) -> usize {
+        match self {
+            Self::NumDups => usize::from(num_dups < num_dups_threshold),
+            Self::ArrivalRank => num_dups_threshold.saturating_sub(num_dups),
+            Self::ArrivalDelay { max_delay } => {
+                let max_delay = max_delay.as_micros();
+                let delay = delay.as_micros();
+                if delay >= max_delay {
+                    0
+                } else {
+                    // ceil((max_delay - delay) * MAX_DELAY_SCORE / max_delay)
+                    let score = (max_delay - delay) * Self::MAX_DELAY_SCORE + max_delay - 1;
+                    (score / max_delay) as usize
+                }
+            }
+        }
+    }
+}
+
+impl ReceivedCacheEntry {
+    fn record(&mut self, node: NodeId, num_dups: usize, score: usize, capacity: usize) {
         if num_dups == 0 {
             self.num_upserts = self.num_upserts.saturating_add(1);
         }
         // If the message has been timely enough increment node's score.
-        if num_dups < Self::NUM_DUPS_THRESHOLD {
-            let score = self.nodes.entry(node).or_default();
-            *score = score.saturating_add(1);
-        } else if self.nodes.len() < Self::CAPACITY {
+        if score > 0 {
+            let entry = self.nodes.entry(node).or_default();
+            *entry = entry.saturating_add(score);
+        } else if self.nodes.len() < capacity {
             // Ensure that node is inserted into the cache for later pruning.
             // This intentionally does not negatively impact node's score, in
             // order to prevent replayed messages with spoofed addresses force
@@ -92,35 +250,85 @@ impl ReceivedCacheEntry {
 
     fn prune(
         self,
-        pubkey: &Pubkey, // This node.
-        origin: &Pubkey, // CRDS value owner.
+        node: NodeId,   // This node.
+        origin: NodeId, // CRDS value owner.
         stake_threshold: f64,
         min_ingress_nodes: usize,
-        stakes: &HashMap<Pubkey, u64>,
-    ) -> impl Iterator<Item = Pubkey> {
+        stakes: &[u64], // Indexed by NodeId.
+    ) -> PruneDecision {
         debug_assert!((0.0..=1.0).contains(&stake_threshold));
-        debug_assert!(self.num_upserts >= ReceivedCache::MIN_NUM_UPSERTS);
         // Enforce a minimum aggregate ingress stake; see:
         // https://github.com/solana-labs/solana/issues/3214
         let min_ingress_stake = {
-            let stake = stakes.get(pubkey).min(stakes.get(origin));
-            (stake.copied().unwrap_or_default() as f64 * stake_threshold) as u64
+            let stake = stakes[node.index()].min(stakes[origin.index()]);
+            (stake as f64 * stake_threshold) as u64
         };
-        self.nodes
+        let candidates = self
+            .nodes
             .into_iter()
-            .map(|(node, score)| {
-                let stake = stakes.get(&node).copied().unwrap_or_default();
-                (node, score, stake)
-            })
-            .sorted_unstable_by_key(|&(_, score, stake)| Reverse((score, stake)))
-            .scan(0u64, |acc, (node, _score, stake)| {
-                let old = *acc;
+            .map(|(node, score)| (node, score, stakes[node.index()]))
+            // Ties are broken by NodeId, so that decisions do not depend on
+            // the iteration order of the hash map.
+            .sorted_unstable_by_key(|&(node, score, stake)| (Reverse((score, stake)), node))
+            .scan(0u64, |acc, (node, score, stake)| {
+                let ingress_stake = *acc;
                 *acc = acc.saturating_add(stake);
-                Some((node, old))
+                Some((node, score, stake, ingress_stake))
+            })
+            .enumerate()
+            .map(|(k, (node, score, stake, ingress_stake))| PruneCandidate {
+                node,
+                score,
+                stake,
+                ingress_stake,
+                // Since the ingress stake is non-decreasing, once the first
+                // min_ingress_nodes are retained the remaining nodes are
+                // pruned as soon as the min ingress stake is reached.
+                pruned: k >= min_ingress_nodes
+                    && ingress_stake >= min_ingress_stake
+                    && node != origin,
             })
-            .skip(min_ingress_nodes)
-            .skip_while(move |&(_, stake)| stake < min_ingress_stake)
-            .map(|(node, _stake)| node)
+            .collect();
+        PruneDecision {
+            node,
+            origin,
+            min_ingress_nodes,
+            min_ingress_stake,
+            candidates,
+        }
+    }
+}
+
+impl PruneDecision {
+    /// Returns nodes which should be pruned for the origin.
+    pub fn pruned(self) -> impl Iterator<Item = NodeId> {
+        self.candidates
+            .into_iter()
+            .filter(|candidate| candidate.pruned)
+            .map(|candidate| candidate.node)
+    }
+}
+
+impl Display for PruneDecision {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "node={} origin={} min_ingress_nodes={} min_ingress_stake={} candidates=[",
+            self.node, self.origin, self.min_ingress_nodes, self.min_ingress_stake,
+        )?;
+        for (k, candidate) in self.candidates.iter().enumerate() {
+            let sep = if k == 0 { "" } else { " " };
+            write!(
+                f,
+                "{sep}{}:score={}:stake={}:ingress_stake={}:{}",
+                candidate.node,
+                candidate.score,
+                candidate.stake,
+                candidate.ingress_stake,
+                if candidate.pruned { "pruned" } else { "kept" },
+            )?;
+        }
+        write!(f, "]")
     }
 }
 
@@ -128,14 +336,21 @@ impl ReceivedCacheEntry {
 mod tests {
     use {
         super::*,
-        std::{collections::HashSet, iter::repeat_with},
+        proptest::{collection::vec, prop_assert, prop_assert_eq, proptest},
+        std::collections::HashSet,
     };
 
     #[test]
     fn test_received_cache() {
-        let mut cache = ReceivedCache::new(/*capacity:*/ 100);
-        let pubkey = Pubkey::new_unique();
-        let origin = Pubkey::new_unique();
+        let mut cache = ReceivedCache::new(
+            100, // capacity
+            20,  // min_num_upserts
+            50,  // entry_capacity
+            2,   // num_dups_threshold
+            ReceivedCacheScorer::NumDups,
+        );
+        let node = NodeId(5);
+        let origin = NodeId(6);
         let records = vec![
             vec![3, 1, 7, 5],
             vec![7, 6, 5, 2],
@@ -143,18 +358,16 @@ mod tests {
             vec![3, 5, 0, 6],
             vec![6, 2, 6, 2],
         ];
-        let nodes: Vec<_> = repeat_with(Pubkey::new_unique)
-            .take(records.len())
-            .collect();
+        let nodes: Vec<_> = (0..records.len()).map(NodeId::from).collect();
         for (node, records) in nodes.iter().zip(records) {
             for (num_dups, k) in records.into_iter().enumerate() {
                 for _ in 0..k {
-                    cache.record(origin, *node, num_dups);
+                    cache.record(origin, *node, num_dups, Duration::ZERO);
                 }
             }
         }
-        assert_eq!(cache.0.get(&origin).unwrap().num_upserts, 21);
-        let scores: HashMap<Pubkey, usize> = [
+        assert_eq!(cache.cache.get(&origin).unwrap().num_upserts, 21);
+        let scores: HashMap<NodeId, usize> = [
             (nodes[0], 4),
             (nodes[1], 13),
             (nodes[2], 2),
@@ -163,32 +376,127 @@ mod tests {
         ]
         .into_iter()
         .collect();
-        assert_eq!(cache.0.get(&origin).unwrap().nodes, scores);
-        let stakes = [
-            (nodes[0], 6),
-            (nodes[1], 1),
-            (nodes[2], 5),
-            (nodes[3], 3),
-            (nodes[4], 7),
-            (pubkey, 9),
-            (origin, 9),
-        ]
-        .into_iter()
-        .collect();
-        let prunes: HashSet<Pubkey> = [nodes[0], nodes[2], nodes[3]].into_iter().collect();
+        assert_eq!(cache.cache.get(&origin).unwrap().nodes, scores);
+        // Indexed by NodeId: nodes, this node and the origin.
+        let stakes = [6, 1, 5, 3, 7, 9, 9];
+        let prunes: HashSet<NodeId> = [nodes[0], nodes[2], nodes[3]].into_iter().collect();
         assert_eq!(
             cache
-                .mock_clone()
-                .prune(&pubkey, origin, 0.5, 2, &stakes)
+                .clone()
+                .prune(node, origin, 0.5, 2, &stakes)
+                .into_iter()
+                .flat_map(PruneDecision::pruned)
                 .collect::<HashSet<_>>(),
             prunes
         );
-        let prunes: HashSet<Pubkey> = [nodes[0], nodes[2]].into_iter().collect();
+        // Cache survives a serialization round trip.
+        let bytes = bincode::serialize(&cache).unwrap();
+        assert_eq!(
+            bincode::deserialize::<ReceivedCache>(&bytes)
+                .unwrap()
+                .prune(node, origin, 0.5, 2, &stakes)
+                .into_iter()
+                .flat_map(PruneDecision::pruned)
+                .collect::<HashSet<_>>(),
+            prunes
+        );
+        let entry = cache.cache.peek(&origin).unwrap().clone();
+        let decision = entry.prune(node, origin, 1.0, 0, &stakes);
+        assert_eq!(decision.min_ingress_stake, 9);
+        assert_eq!(
+            decision
+                .candidates
+                .iter()
+                .map(|candidate| (candidate.node, candidate.ingress_stake, candidate.pruned))
+                .collect::<Vec<_>>(),
+            [
+                (nodes[1], 0, false),
+                (nodes[4], 1, false),
+                (nodes[3], 8, false),
+                (nodes[0], 11, true),
+                (nodes[2], 17, true),
+            ]
+        );
+        let prunes: HashSet<NodeId> = [nodes[0], nodes[2]].into_iter().collect();
         assert_eq!(
             cache
-                .prune(&pubkey, origin, 1.0, 0, &stakes)
+                .prune(node, origin, 1.0, 0, &stakes)
+                .into_iter()
+                .flat_map(PruneDecision::pruned)
                 .collect::<HashSet<_>>(),
             prunes
         );
     }
+
+    #[test]
+    fn test_received_cache_scorer() {
+        let scorer = ReceivedCacheScorer::NumDups;
+        let scores: Vec<_> = (0..4)
+            .map(|num_dups| scorer.score(num_dups, Duration::ZERO, 2))
+            .collect();
+        assert_eq!(scores, [1, 1, 0, 0]);
+        let scorer = ReceivedCacheScorer::ArrivalRank;
+        let scores: Vec<_> = (0..5)
+            .map(|num_dups| scorer.score(num_dups, Duration::ZERO, 3))
+            .collect();
+        assert_eq!(scores, [3, 2, 1, 0, 0]);
+        assert_eq!(scorer.score(usize::MAX, Duration::MAX, 3), 0);
+        let scorer = ReceivedCacheScorer::ArrivalDelay {
+            max_delay: Duration::from_millis(100),
+        };
+        let scores: Vec<_> = [0, 1, 10, 55, 99, 100, 200]
+            .into_iter()
+            .map(|delay| scorer.score(1, Duration::from_millis(delay), 2))
+            .collect();
+        assert_eq!(scores, [10, 10, 9, 5, 1, 0, 0]);
+        assert_eq!(scorer.score(usize::MAX, Duration::MAX, 2), 0);
+    }
+
+    proptest! {
+        #[test]
+        fn prop_received_cache_entry_prune(
+            stakes in vec(0u64..1_000_000, 2..40), // Indexed by NodeId.
+            scores in vec((0usize..40, 0usize..8), 0..40),
+            node in 0usize..40,
+            origin in 0usize..40,
+            stake_threshold in 0.0..=1.0f64,
+            min_ingress_nodes in 0usize..8,
+        ) {
+            let node = NodeId::from(node % stakes.len());
+            let origin = NodeId::from(origin % stakes.len());
+            let nodes: HashMap<NodeId, /*score:*/ usize> = scores
+                .into_iter()
+                .map(|(node, score)| (NodeId::from(node % stakes.len()), score))
+                .collect();
+            let entry = ReceivedCacheEntry {
+                nodes: nodes.clone(),
+                num_upserts: 0,
+            };
+            let decision = entry.prune(node, origin, stake_threshold, min_ingress_nodes, &stakes);
+            prop_assert_eq!(decision.candidates.len(), nodes.len());
+            prop_assert!(decision
+                .candidates
+                .iter()
+                .all(|candidate| nodes.get(&candidate.node) == Some(&candidate.score)));
+            // Candidates are ranked by (score, stake), accumulating stake.
+            for (a, b) in decision.candidates.iter().tuple_windows() {
+                prop_assert!((a.score, a.stake) >= (b.score, b.stake));
+                prop_assert_eq!(b.ingress_stake, a.ingress_stake + a.stake);
+            }
+            let kept: Vec<&PruneCandidate> = decision
+                .candidates
+                .iter()
+                .filter(|candidate| !candidate.pruned)
+                .collect();
+            prop_assert!(kept.len() >= min_ingress_nodes.min(nodes.len()));
+            let kept_stake: u64 = kept.iter().map(|candidate| candidate.stake).sum();
+            let total_stake: u64 = decision.candidates.iter().map(|candidate| candidate.stake).sum();
+            if total_stake >= decision.min_ingress_stake {
+                prop_assert!(kept_stake >= decision.min_ingress_stake, "{}", decision);
+            } else {
+                prop_assert_eq!(kept.len(), nodes.len());
+            }
+            prop_assert!(!decision.pruned().any(|node| node == origin));
+        }
+    }
 }
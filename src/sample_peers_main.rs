@@ -1,66 +1,159 @@
 use {
     clap::{crate_description, crate_name, App, Arg},
-    cluster_mocks::{gossip::make_gossip_cluster, API_MAINNET_BETA},
+    cluster_mocks::{
+        gossip::get_cluster_stakes,
+        push_active_set::{get_sampling_weight, get_stake_bucket, NUM_PUSH_ACTIVE_SET_ENTRIES},
+        weighted_sampling::get_inclusion_probabilities,
+        API_MAINNET_BETA,
+    },
     log::info,
     rand::Rng,
     solana_client::rpc_client::RpcClient,
     solana_gossip::weighted_shuffle::WeightedShuffle,
     solana_sdk::pubkey::Pubkey,
-    std::{
-        cmp::Reverse,
-        collections::HashMap,
-        time::{Duration, Instant},
-    },
+    std::{cmp::Reverse, collections::HashMap},
 };
 
 #[derive(Debug)]
 struct Config {
     gossip_push_fanout: usize,
     num_rounds: usize,
-    round_delay: Duration,
+    // Node sampling peers; if None, iterates over all nodes.
+    node: Option<Pubkey>,
+    // Active-set entry to sample peers for. Defaults to the stake bucket of
+    // the node, which is the entry used for crds values from origins with
+    // at least as much stake as the node.
+    bucket: Option<usize>,
 }
 
-fn get_weight(pubkey: &Pubkey, stakes: &HashMap<Pubkey, u64>) -> u64 {
-    // TODO: Needs to min with this node's stake!
-    // TODO: no need to run sim here anymore!
-    let stake = stakes.get(pubkey).copied().unwrap_or_default();
-    let weight = u64::BITS - stake.leading_zeros();
-    u64::from(weight).saturating_add(1).saturating_pow(2)
+// Returns the weights with which gossip peers are sampled into the node's
+// active-set entry k, same as PushActiveSet::rotate.
+fn get_weights(node: usize, k: usize, stakes: &[(Pubkey, /*stake:*/ u64)]) -> Vec<u64> {
+    stakes
+        .iter()
+        .enumerate()
+        .map(|(other, (_pubkey, stake))| {
+            if other == node {
+                0 // A node never samples itself.
+            } else {
+                get_sampling_weight(k, get_stake_bucket(Some(stake)))
+            }
+        })
+        .collect()
 }
 
-fn run_sample_peers<R: Rng>(rng: &mut R, config: &Config, stakes: &HashMap<Pubkey, u64>) {
-    let mut now = Instant::now();
-    let mut hits = HashMap::<Pubkey, usize>::with_capacity(stakes.len());
-    for _ in 0..config.num_rounds {
-        let (nodes, weights): (Vec<_>, Vec<_>) = stakes
-            .keys()
-            .map(|pubkey| (*pubkey, get_weight(pubkey, stakes)))
-            .unzip();
-        let shuffle = WeightedShuffle::new("run-sample-peers", &weights).shuffle(rng);
-        for k in shuffle.take(config.gossip_push_fanout) {
-            let node = nodes[k];
-            *hits.entry(node).or_default() += 1;
+fn run_sample_peers<R: Rng>(rng: &mut R, config: &Config, stakes: &[(Pubkey, /*stake:*/ u64)]) {
+    let nodes: Vec<usize> = match config.node {
+        None => (0..stakes.len()).collect(),
+        Some(node) => {
+            let node = stakes
+                .iter()
+                .position(|(pubkey, _stake)| pubkey == &node)
+                .unwrap_or_else(|| panic!("node not found: {node}"));
+            vec![node]
+        }
+    };
+    // Expected number of times each peer is selected per round, and the
+    // sampled number of times across all rounds.
+    let mut expected = vec![0.0; stakes.len()];
+    let mut hits = vec![0usize; stakes.len()];
+    // Inclusion probabilities only depend on the weight of each peer, given
+    // the active-set entry and the weight excluded for the sampling node.
+    let mut cache = HashMap::<(/*k:*/ usize, /*weight:*/ u64), HashMap<u64, f64>>::new();
+    for node in nodes {
+        let k = config
+            .bucket
+            .unwrap_or_else(|| get_stake_bucket(Some(&stakes[node].1)));
+        let weights = get_weights(node, k, stakes);
+        if config.node.is_some() {
+            let total_weight: u64 = weights.iter().sum();
+            info!(
+                "node: {}, bucket: {}, total weight: {}",
+                stakes[node].0, k, total_weight
+            );
+        }
+        let self_weight = get_sampling_weight(k, get_stake_bucket(Some(&stakes[node].1)));
+        let probs = cache.entry((k, self_weight)).or_insert_with(|| {
+            let probs = get_inclusion_probabilities(&weights, config.gossip_push_fanout);
+            weights.iter().copied().zip(probs).collect()
+        });
+        for (other, (expected, weight)) in expected.iter_mut().zip(&weights).enumerate() {
+            if other != node {
+                *expected += probs[weight];
+            }
+        }
+        for _ in 0..config.num_rounds {
+            let shuffle = WeightedShuffle::new("run-sample-peers", &weights).shuffle(rng);
+            for k in shuffle.take(config.gossip_push_fanout) {
+                hits[k] += 1;
+            }
         }
-        now += config.round_delay;
     }
-    let active_stake: u64 = stakes.values().sum();
-    let mut hits: Vec<_> = stakes
-        .iter()
-        .map(|(&pubkey, &stake)| {
-            let hits = hits.get(&pubkey).copied().unwrap_or_default();
-            (pubkey, stake, hits)
-        })
-        .collect();
-    hits.sort_unstable_by_key(|(_pubkey, stake, _hits)| Reverse(*stake));
-    println!("node     | stake  | stake | hits");
-    for (pubkey, stake, hits) in hits {
-        println!(
-            "{} | {:.3}% | {:5.2} | {:5}",
+    let active_stake: u64 = stakes.iter().map(|(_pubkey, stake)| stake).sum();
+    println!("node     | stake  | bucket |  expected |  sampled | deviation");
+    println!("--------------------------------------------------------------");
+    for (k, (pubkey, stake)) in stakes.iter().enumerate() {
+        if Some(pubkey) == config.node.as_ref() {
+            continue;
+        }
+        print!(
+            "{} | {:.3}% | {:6} | {:9.4}",
             &format!("{pubkey}")[..8],
-            stake as f64 * 100.0 / active_stake as f64,
-            get_weight(&pubkey, stakes),
-            hits
+            *stake as f64 * 100.0 / active_stake as f64,
+            get_stake_bucket(Some(stake)),
+            expected[k],
         );
+        print_sampled(config, expected[k], hits[k]);
+    }
+    print_bucket_stats(config, stakes, &expected, &hits);
+}
+
+// Prints expected versus sampled selections aggregated by stake bucket of
+// the selected peers.
+fn print_bucket_stats(
+    config: &Config,
+    stakes: &[(Pubkey, /*stake:*/ u64)],
+    expected: &[f64],
+    hits: &[usize],
+) {
+    let mut buckets = [(
+        /*num_peers:*/ 0usize, /*expected:*/ 0.0, /*hits:*/ 0usize,
+    ); NUM_PUSH_ACTIVE_SET_ENTRIES];
+    for (k, (pubkey, stake)) in stakes.iter().enumerate() {
+        if Some(pubkey) == config.node.as_ref() {
+            continue;
+        }
+        let bucket = &mut buckets[get_stake_bucket(Some(stake))];
+        bucket.0 += 1;
+        bucket.1 += expected[k];
+        bucket.2 += hits[k];
+    }
+    println!("bucket | peers |  expected |  sampled | deviation");
+    println!("-------------------------------------------------");
+    for (k, &(num_peers, expected, hits)) in buckets.iter().enumerate() {
+        if num_peers == 0 {
+            continue;
+        }
+        print!("{k:6} | {num_peers:5} | {expected:9.4}");
+        print_sampled(config, expected, hits);
+    }
+}
+
+// Prints the sampled number of selections per round and its deviation from
+// the expected value, if any rounds were simulated.
+fn print_sampled(config: &Config, expected: f64, hits: usize) {
+    if config.num_rounds == 0 {
+        println!();
+        return;
+    }
+    let sampled = hits as f64 / config.num_rounds as f64;
+    if expected > 0.0 {
+        println!(
+            " | {sampled:8.4} | {:+8.2}%",
+            (sampled - expected) * 100.0 / expected
+        );
+    } else {
+        println!(" | {sampled:8.4} | {:>9}", "-");
     }
 }
 
@@ -91,32 +184,45 @@ fn main() {
             Arg::with_name("num_rounds")
                 .long("num-rounds")
                 .takes_value(true)
-                .default_value("10000")
-                .help("number of rounds to simulate"),
+                .default_value("0")
+                .help("number of rounds to simulate for each node, to compare against exact probabilities"),
+        )
+        .arg(
+            Arg::with_name("node")
+                .long("node")
+                .value_name("PUBKEY")
+                .takes_value(true)
+                .help("node sampling peers; iterates over all nodes if not specified"),
         )
         .arg(
-            Arg::with_name("round_delay")
-                .long("round-delay")
+            Arg::with_name("bucket")
+                .long("bucket")
                 .takes_value(true)
-                .default_value("200")
-                .help("delay between rounds [ms]"),
+                .validator(|bucket| match bucket.parse::<usize>() {
+                    Ok(bucket) if bucket < NUM_PUSH_ACTIVE_SET_ENTRIES => Ok(()),
+                    Ok(_) => Err(format!("must be less than {NUM_PUSH_ACTIVE_SET_ENTRIES}")),
+                    Err(err) => Err(err.to_string()),
+                })
+                .help("active-set entry to sample peers for; defaults to node's stake bucket"),
         )
         .get_matches();
     let config = Config {
         gossip_push_fanout: matches.value_of_t_or_exit("gossip_push_fanout"),
         num_rounds: matches.value_of_t_or_exit("num_rounds"),
-        round_delay: Duration::from_millis(matches.value_of_t_or_exit("round_delay")),
+        node: matches
+            .is_present("node")
+            .then(|| matches.value_of_t_or_exit("node")),
+        bucket: matches
+            .is_present("bucket")
+            .then(|| matches.value_of_t_or_exit("bucket")),
     };
     info!("config: {:#?}", config);
     let json_rpc_url =
         cluster_mocks::get_json_rpc_url(matches.value_of("json_rpc_url").unwrap_or_default());
     info!("json_rpc_url: {}", json_rpc_url);
     let rpc_client = RpcClient::new(json_rpc_url);
-    let stakes: HashMap<Pubkey, /*stake:*/ u64> = make_gossip_cluster(&rpc_client)
-        .unwrap()
-        .into_iter()
-        .map(|(node, _sender)| (node.pubkey(), node.stake()))
-        .collect();
+    let mut stakes = get_cluster_stakes(&rpc_client).unwrap();
+    stakes.sort_unstable_by_key(|(_pubkey, stake)| Reverse(*stake));
     let mut rng = rand::thread_rng();
     run_sample_peers(&mut rng, &config, &stakes);
 }
@@ -0,0 +1,192 @@
+use {
+    crate::{
+        gossip::{Config, Node, Packet},
+        mix64,
+        simulation::Observer,
+        Error, Router,
+    },
+    std::{
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc, RwLock,
+        },
+        time::Instant,
+    },
+};
+
+/// Schedules gossip rounds of nodes across threads in epochs, where each
+/// epoch runs every node exactly once. Threads claim slots from a shared
+/// counter and block on the node's lock instead of polling random nodes, so
+/// that once all threads return, round counts across nodes differ by at
+/// most one.
+pub struct Scheduler {
+    num_nodes: usize,
+    // Number of slots claimed so far, across all epochs.
+    counter: AtomicUsize,
+}
+
+/// Distribution of the number of gossip rounds each node has run.
+#[derive(Clone, Debug)]
+pub struct RoundStats {
+    pub min: usize,
+    pub max: usize,
+    pub mean: f64,
+    pub variance: f64,
+}
+
+impl Scheduler {
+    pub fn new(num_nodes: usize) -> Self {
+        assert!(num_nodes > 0, "empty cluster");
+        Self {
+            num_nodes,
+            counter: AtomicUsize::default(),
+        }
+    }
+
+    /// Runs gossip rounds of scheduled nodes on the calling thread until
+    /// the deadline. Intended to be called from each worker thread.
+    pub fn run(
+        &self,
+        config: &Config,
+        nodes: &[RwLock<Node>],
+        router: &Router<Arc<Packet>>,
+        observer: &dyn Observer,
+        deadline: Instant,
+    ) -> Result<(), Error> {
+        while Instant::now() < deadline {
+            self.step(config, nodes, router, observer)?;
+        }
+        Ok(())
+    }
+
+    /// Runs gossip rounds of scheduled nodes on the calling thread up to the
+    /// end of the current epoch. Must not run concurrently with other calls.
+    pub fn run_epoch(
+        &self,
+        config: &Config,
+        nodes: &[RwLock<Node>],
+        router: &Router<Arc<Packet>>,
+        observer: &dyn Observer,
+    ) -> Result<(), Error> {
+        let num_slots = self.num_nodes - self.counter.load(Ordering::Relaxed) % self.num_nodes;
+        for _ in 0..num_slots {
+            self.step(config, nodes, router, observer)?;
+        }
+        Ok(())
+    }
+
+    /// Runs one gossip round of the next scheduled node.
+    pub fn step(
+        &self,
+        config: &Config,
+        nodes: &[RwLock<Node>],
+        router: &Router<Arc<Packet>>,
+        observer: &dyn Observer,
+    ) -> Result<(), Error> {
+        assert_eq!(nodes.len(), self.num_nodes);
+        let node = &nodes[self.next_node()];
+        let mut node = node.write().map_err(|_| Error::LockErrorPoisoned)?;
+        node.run_gossip(config, router, observer)
+    }
+
+    // Claims the next slot and returns the index of the node to run.
+    fn next_node(&self) -> usize {
+        let slot = self.counter.fetch_add(1, Ordering::Relaxed);
+        let (epoch, slot) = (slot / self.num_nodes, slot % self.num_nodes);
+        // Nodes are visited in a different order each epoch, using an affine
+        // permutation k -> (a * k + b) mod n, with a coprime to n, so that
+        // no node is consistently scheduled ahead of others.
+        let n = self.num_nodes as u64;
+        let hash = mix64(epoch as u64);
+        let mut a = (hash % n).max(1);
+        while gcd(a, n) != 1 {
+            a += 1;
+        }
+        let b = (hash >> 32) % n;
+        ((a as u128 * slot as u128 + b as u128) % n as u128) as usize
+    }
+}
+
+impl RoundStats {
+    /// Returns None if there are no nodes.
+    pub fn new<I>(rounds: I) -> Option<Self>
+    where
+        I: IntoIterator<Item = usize>,
+    {
+        let rounds: Vec<usize> = rounds.into_iter().collect();
+        let min = rounds.iter().copied().min()?;
+        let max = rounds.iter().copied().max()?;
+        let mean = rounds.iter().sum::<usize>() as f64 / rounds.len() as f64;
+        let variance = rounds
+            .iter()
+            .map(|&num_rounds| (num_rounds as f64 - mean).powi(2))
+            .sum::<f64>()
+            / rounds.len() as f64;
+        Some(Self {
+            min,
+            max,
+            mean,
+            variance,
+        })
+    }
+}
+
+fn gcd(mut a: u64, mut b: u64) -> u64 {
+    while b != 0 {
+        (a, b) = (b, a % b);
+    }
+    a
+}
+
+#[cfg(test)]
+mod tests {
+    use {super::*, std::collections::HashSet};
+
+    #[test]
+    fn test_scheduler_epochs() {
+        for num_nodes in [1, 2, 7, 12, 100, 257] {
+            let scheduler = Scheduler::new(num_nodes);
+            let mut orders = HashSet::new();
+            for _ in 0..8 {
+                let order: Vec<usize> = (0..num_nodes).map(|_| scheduler.next_node()).collect();
+                // Each epoch runs every node exactly once.
+                let nodes: HashSet<usize> = order.iter().copied().collect();
+                assert_eq!(nodes.len(), num_nodes);
+                assert!(nodes.iter().all(|&node| node < num_nodes));
+                orders.insert(order);
+            }
+            // Order of nodes changes across epochs.
+            if num_nodes > 7 {
+                assert!(orders.len() > 1);
+            }
+        }
+    }
+
+    #[test]
+    fn test_scheduler_concurrent() {
+        const NUM_NODES: usize = 37;
+        let scheduler = Scheduler::new(NUM_NODES);
+        let rounds: Vec<_> = (0..NUM_NODES).map(|_| AtomicUsize::default()).collect();
+        std::thread::scope(|scope| {
+            for _ in 0..4 {
+                scope.spawn(|| {
+                    for _ in 0..1000 {
+                        rounds[scheduler.next_node()].fetch_add(1, Ordering::Relaxed);
+                    }
+                });
+            }
+        });
+        let stats = RoundStats::new(rounds.iter().map(|r| r.load(Ordering::Relaxed))).unwrap();
+        assert!(stats.max - stats.min <= 1);
+        assert!((stats.mean - 4000.0 / NUM_NODES as f64).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_round_stats() {
+        assert!(RoundStats::new([]).is_none());
+        let stats = RoundStats::new([2, 4, 4, 4, 5, 5, 7, 9]).unwrap();
+        assert_eq!((stats.min, stats.max), (2, 9));
+        assert!((stats.mean - 5.0).abs() < 1e-9);
+        assert!((stats.variance - 4.0).abs() < 1e-9);
+    }
+}
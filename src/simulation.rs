@@ -0,0 +1,507 @@
+use {
+    crate::{
+        checkpoint::Checkpoint,
+        gossip::{get_cluster_stakes, make_gossip_nodes, Config, CrdsKey, Node, Packet, Trace},
+        received_cache::PruneDecision,
+        scheduler::Scheduler,
+        Error, NodeId, Router, RouterError,
+    },
+    rand::{Rng, SeedableRng},
+    rand_chacha::ChaChaRng,
+    rayon::{prelude::*, ThreadPool, ThreadPoolBuilder},
+    solana_client::rpc_client::RpcClient,
+    solana_sdk::pubkey::Pubkey,
+    std::{
+        sync::{Arc, RwLock, RwLockReadGuard},
+        time::Instant,
+    },
+};
+
+/// Callbacks invoked as the simulation runs. Callbacks may be invoked
+/// concurrently from worker threads, and default to no-ops.
+pub trait Observer: Send + Sync {
+    /// A node sends a packet to another node, before the network may drop
+    /// the packet.
+    fn on_packet_sent(&self, _from: NodeId, _to: NodeId, _packet: &Packet) {}
+    /// A node takes a packet off its channel.
+    fn on_packet_received(&self, _node: NodeId, _packet: &Packet) {}
+    /// A node inserts a new crds value into its table, including refreshes
+    /// of its own values.
+    fn on_upsert(&self, _node: NodeId, _key: CrdsKey, _ordinal: u64) {}
+    /// A node prunes its received-cache entry for an origin.
+    fn on_prune(&self, _decision: &PruneDecision) {}
+    /// A node rotates its push active set.
+    fn on_rotation(&self, _node: NodeId) {}
+}
+
+impl Observer for () {}
+
+/// Where nodes of the simulated cluster come from.
+pub enum ClusterSource {
+    /// Staked nodes of the cluster at the given json rpc url.
+    Rpc(String),
+    /// Nodes with the given pubkeys and stakes.
+    Stakes(Vec<(Pubkey, /*stake:*/ u64)>),
+    /// Warm cluster restored from a checkpoint.
+    Checkpoint(Checkpoint),
+}
+
+/// How packets are delivered between nodes.
+#[derive(Clone, Copy, Debug)]
+pub struct NetworkModel {
+    pub packet_drop_rate: f64,
+}
+
+#[derive(Default)]
+pub struct SimulationBuilder {
+    source: Option<ClusterSource>,
+    config: Option<Config>,
+    network: Option<NetworkModel>,
+    seed: Option<u64>,
+    observers: Vec<Arc<dyn Observer>>,
+}
+
+/// A simulated gossip cluster, which can be stepped one round at a time or
+/// run across worker threads.
+pub struct Simulation {
+    config: Config,
+    // Indexed by NodeId.
+    nodes: Vec<RwLock<Node>>,
+    pubkeys: Vec<Pubkey>,
+    stakes: Vec<u64>,
+    router: Router<Arc<Packet>>,
+    scheduler: Scheduler,
+    thread_pool: ThreadPool,
+    observers: Observers,
+}
+
+// Forwards each callback to all observers in order.
+struct Observers(Vec<Arc<dyn Observer>>);
+
+impl SimulationBuilder {
+    pub fn cluster(mut self, source: ClusterSource) -> Self {
+        self.source = Some(source);
+        self
+    }
+
+    pub fn config(mut self, config: Config) -> Self {
+        self.config = Some(config);
+        self
+    }
+
+    /// Defaults to the packet drop rate in the config.
+    pub fn network(mut self, network: NetworkModel) -> Self {
+        self.network = Some(network);
+        self
+    }
+
+    /// Seeds the nodes' rngs. When resuming from a checkpoint, nodes keep
+    /// their checkpointed rngs unless a seed is given, which forks the run.
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.seed = Some(seed);
+        self
+    }
+
+    pub fn observer(mut self, observer: Arc<dyn Observer>) -> Self {
+        self.observers.push(observer);
+        self
+    }
+
+    pub fn build(self) -> Result<Simulation, Error> {
+        let config = self
+            .config
+            .ok_or_else(|| Error::InvalidSimulation(String::from("missing config")))?;
+        let source = self
+            .source
+            .ok_or_else(|| Error::InvalidSimulation(String::from("missing cluster source")))?;
+        if config.gossip_prune_max_origins == 0 {
+            return Err(Error::InvalidSimulation(String::from(
+                "gossip_prune_max_origins must be positive",
+            )));
+        }
+        if config.crds_purge_rounds == 0 {
+            return Err(Error::InvalidSimulation(String::from(
+                "crds_purge_rounds must be positive",
+            )));
+        }
+        let mut rng = match self.seed {
+            None => ChaChaRng::from_seed(rand::thread_rng().gen()),
+            Some(seed) => ChaChaRng::seed_from_u64(seed),
+        };
+        let nodes = match source {
+            ClusterSource::Rpc(json_rpc_url) => {
+                let stakes = get_cluster_stakes(&RpcClient::new(json_rpc_url))?;
+                make_gossip_nodes(&mut rng, stakes, &config)
+            }
+            ClusterSource::Stakes(stakes) => make_gossip_nodes(&mut rng, stakes, &config),
+            ClusterSource::Checkpoint(checkpoint) => {
+                let mut nodes = checkpoint.into_nodes();
+                if self.seed.is_some() {
+                    for (node, _sender) in &mut nodes {
+                        node.reseed(rng.gen());
+                    }
+                }
+                nodes
+            }
+        };
+        if nodes.is_empty() {
+            return Err(Error::InvalidSimulation(String::from("empty cluster")));
+        }
+        let network = self.network.unwrap_or(NetworkModel {
+            packet_drop_rate: config.packet_drop_rate,
+        });
+        let (nodes, senders): (Vec<Node>, Vec<_>) = nodes.into_iter().unzip();
+        let router = Router::new(network.packet_drop_rate, senders)?;
+        let thread_pool = ThreadPoolBuilder::new()
+            .num_threads(config.num_threads)
+            .build()?;
+        Ok(Simulation {
+            config,
+            pubkeys: nodes.iter().map(Node::pubkey).collect(),
+            stakes: nodes.iter().map(Node::stake).collect(),
+            scheduler: Scheduler::new(nodes.len()),
+            nodes: nodes.into_iter().map(RwLock::new).collect(),
+            router,
+            thread_pool,
+            observers: Observers(self.observers),
+        })
+    }
+}
+
+impl Simulation {
+    pub fn builder() -> SimulationBuilder {
+        SimulationBuilder::default()
+    }
+
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    /// Indexed by NodeId.
+    pub fn pubkeys(&self) -> &[Pubkey] {
+        &self.pubkeys
+    }
+
+    /// Stakes of the cluster, indexed by NodeId. Nodes consult their own
+    /// view of stakes, which may differ.
+    pub fn stakes(&self) -> &[u64] {
+        &self.stakes
+    }
+
+    /// Starts a new epoch with the given stakes, indexed by NodeId, which
+    /// each node adopts into its view of stakes after a random lag.
+    pub fn set_epoch_stakes(&mut self, stakes: Vec<u64>) -> Result<(), Error> {
+        if stakes.len() != self.nodes.len() {
+            return Err(Error::InvalidSimulation(format!(
+                "epoch stakes for {} nodes, expected {}",
+                stakes.len(),
+                self.nodes.len()
+            )));
+        }
+        let stakes = Arc::<[u64]>::from(stakes);
+        for node in &mut self.nodes {
+            let node = node.get_mut().map_err(|_| Error::LockErrorPoisoned)?;
+            node.set_epoch_stakes(&self.config, &stakes);
+        }
+        self.stakes = stakes.to_vec();
+        Ok(())
+    }
+
+    pub fn node(&self, node: NodeId) -> Result<RwLockReadGuard<'_, Node>, Error> {
+        self.nodes
+            .get(node.index())
+            .ok_or(RouterError::NodeNotFound(node))?
+            .read()
+            .map_err(|_| Error::LockErrorPoisoned)
+    }
+
+    /// Sets the crds value whose propagation is recorded, from the next
+    /// gossip round onwards.
+    pub fn set_trace(&mut self, trace: Option<Trace>) {
+        self.config.trace = trace;
+    }
+
+    /// Runs gossip rounds on the calling thread, in the order given by the
+    /// scheduler, up to the end of the scheduling epoch. This is one round of
+    /// each node, except after run_until stops mid-epoch, where only nodes
+    /// which have not run in that epoch yet run, evening out round counts.
+    pub fn step(&mut self) -> Result<(), Error> {
+        self.scheduler
+            .run_epoch(&self.config, &self.nodes, &self.router, &self.observers)
+    }
+
+    /// Runs gossip rounds on all worker threads until the deadline.
+    pub fn run_until(&mut self, deadline: Instant) -> Result<(), Error> {
+        self.thread_pool
+            .broadcast(|_ctx| {
+                self.scheduler.run(
+                    &self.config,
+                    &self.nodes,
+                    &self.router,
+                    &self.observers,
+                    deadline,
+                )
+            })
+            .into_iter()
+            .collect()
+    }
+
+    pub fn checkpoint(&mut self) -> Result<Checkpoint, Error> {
+        let nodes = self
+            .nodes
+            .iter_mut()
+            .map(|node| node.get_mut().map_err(|_| Error::LockErrorPoisoned))
+            .collect::<Result<Vec<_>, Error>>()?;
+        Checkpoint::new(nodes, &self.router)
+    }
+
+    /// Consumes packets still in flight and returns the nodes, indexed by
+    /// NodeId.
+    pub fn into_nodes(self) -> Result<Vec<Node>, Error> {
+        let mut nodes = self
+            .nodes
+            .into_iter()
+            .map(|node| node.into_inner().map_err(|_| Error::LockErrorPoisoned))
+            .collect::<Result<Vec<_>, Error>>()?;
+        let (config, observers) = (&self.config, &self.observers);
+        self.thread_pool.install(|| {
+            nodes.par_iter_mut().for_each(|node| {
+                node.consume_packets(config, observers);
+            })
+        });
+        Ok(nodes)
+    }
+}
+
+impl Observer for Observers {
+    fn on_packet_sent(&self, from: NodeId, to: NodeId, packet: &Packet) {
+        for observer in &self.0 {
+            observer.on_packet_sent(from, to, packet);
+        }
+    }
+
+    fn on_packet_received(&self, node: NodeId, packet: &Packet) {
+        for observer in &self.0 {
+            observer.on_packet_received(node, packet);
+        }
+    }
+
+    fn on_upsert(&self, node: NodeId, key: CrdsKey, ordinal: u64) {
+        for observer in &self.0 {
+            observer.on_upsert(node, key, ordinal);
+        }
+    }
+
+    fn on_prune(&self, decision: &PruneDecision) {
+        for observer in &self.0 {
+            observer.on_prune(decision);
+        }
+    }
+
+    fn on_rotation(&self, node: NodeId) {
+        for observer in &self.0 {
+            observer.on_rotation(node);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        crate::stakes::StakeViewModel,
+        std::sync::atomic::{AtomicUsize, Ordering},
+    };
+
+    #[derive(Default)]
+    struct Counts {
+        num_packets_sent: AtomicUsize,
+        num_packets_received: AtomicUsize,
+        num_upserts: AtomicUsize,
+        num_prunes: AtomicUsize,
+        num_rotations: AtomicUsize,
+    }
+
+    impl Observer for Counts {
+        fn on_packet_sent(&self, from: NodeId, to: NodeId, _packet: &Packet) {
+            assert_ne!(from, to);
+            self.num_packets_sent.fetch_add(1, Ordering::Relaxed);
+        }
+
+        fn on_packet_received(&self, _node: NodeId, _packet: &Packet) {
+            self.num_packets_received.fetch_add(1, Ordering::Relaxed);
+        }
+
+        fn on_upsert(&self, _node: NodeId, _key: CrdsKey, _ordinal: u64) {
+            self.num_upserts.fetch_add(1, Ordering::Relaxed);
+        }
+
+        fn on_prune(&self, _decision: &PruneDecision) {
+            self.num_prunes.fetch_add(1, Ordering::Relaxed);
+        }
+
+        fn on_rotation(&self, _node: NodeId) {
+            self.num_rotations.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    #[test]
+    fn test_simulation() {
+        const NUM_NODES: usize = 20;
+        const NUM_STEPS: usize = 30;
+        let config = Config::new_for_tests();
+        assert!(matches!(
+            Simulation::builder().config(config).build(),
+            Err(Error::InvalidSimulation(_))
+        ));
+        let stakes: Vec<_> = (1..=NUM_NODES as u64)
+            .map(|k| (Pubkey::new_unique(), k * 1_000_000_000))
+            .collect();
+        assert!(matches!(
+            Simulation::builder()
+                .cluster(ClusterSource::Stakes(stakes.clone()))
+                .config(Config {
+                    gossip_prune_max_origins: 0,
+                    ..config
+                })
+                .build(),
+            Err(Error::InvalidSimulation(_))
+        ));
+        assert!(matches!(
+            Simulation::builder()
+                .cluster(ClusterSource::Stakes(stakes.clone()))
+                .config(Config {
+                    crds_purge_rounds: 0,
+                    ..config
+                })
+                .build(),
+            Err(Error::InvalidSimulation(_))
+        ));
+        let counts = Arc::new(Counts::default());
+        let mut simulation = Simulation::builder()
+            .cluster(ClusterSource::Stakes(stakes))
+            .config(config)
+            .seed(42)
+            .observer(counts.clone())
+            .build()
+            .unwrap();
+        assert_eq!(simulation.pubkeys().len(), NUM_NODES);
+        for _ in 0..NUM_STEPS {
+            simulation.step().unwrap();
+        }
+        let node = NodeId::from(NUM_NODES - 1);
+        assert_eq!(
+            simulation.node(node).unwrap().num_gossip_rounds(),
+            NUM_STEPS
+        );
+        assert!(matches!(
+            simulation.node(NodeId::from(NUM_NODES)),
+            Err(Error::RouterError(RouterError::NodeNotFound(_)))
+        ));
+        let nodes = simulation.into_nodes().unwrap();
+        assert!(nodes
+            .iter()
+            .all(|node| node.num_gossip_rounds() == NUM_STEPS));
+        // Without packet drops, every packet sent is eventually received.
+        let num_packets_sent = counts.num_packets_sent.load(Ordering::Relaxed);
+        assert!(num_packets_sent > 0);
+        assert_eq!(
+            counts.num_packets_received.load(Ordering::Relaxed),
+            num_packets_sent
+        );
+        assert!(counts.num_upserts.load(Ordering::Relaxed) > 0);
+        assert!(counts.num_prunes.load(Ordering::Relaxed) > 0);
+        // Active sets rotate in rounds 1, 5, ..., 29.
+        assert_eq!(counts.num_rotations.load(Ordering::Relaxed), NUM_NODES * 8);
+    }
+
+    #[test]
+    fn test_step_mid_epoch() {
+        const NUM_NODES: usize = 7;
+        let stakes = (1..=NUM_NODES as u64)
+            .map(|k| (Pubkey::new_unique(), k * 1_000_000_000))
+            .collect();
+        let mut simulation = Simulation::builder()
+            .cluster(ClusterSource::Stakes(stakes))
+            .config(Config::new_for_tests())
+            .seed(42)
+            .build()
+            .unwrap();
+        let get_rounds = |simulation: &Simulation| -> Vec<usize> {
+            (0..NUM_NODES)
+                .map(|k| {
+                    simulation
+                        .node(NodeId::from(k))
+                        .unwrap()
+                        .num_gossip_rounds()
+                })
+                .collect()
+        };
+        // Leave the scheduler mid-epoch, as run_until may.
+        for _ in 0..3 {
+            simulation
+                .scheduler
+                .step(
+                    &simulation.config,
+                    &simulation.nodes,
+                    &simulation.router,
+                    &simulation.observers,
+                )
+                .unwrap();
+        }
+        assert_eq!(get_rounds(&simulation).iter().sum::<usize>(), 3);
+        // Stepping completes the epoch, so that all nodes have run once.
+        simulation.step().unwrap();
+        assert_eq!(get_rounds(&simulation), [1; NUM_NODES]);
+        simulation.step().unwrap();
+        assert_eq!(get_rounds(&simulation), [2; NUM_NODES]);
+    }
+
+    #[test]
+    fn test_epoch_stakes() {
+        const NUM_NODES: usize = 10;
+        const MAX_LAG_ROUNDS: usize = 3;
+        let config = Config {
+            stake_view: StakeViewModel {
+                max_lag_rounds: MAX_LAG_ROUNDS,
+                ..StakeViewModel::EXACT
+            },
+            ..Config::new_for_tests()
+        };
+        let stakes = (1..=NUM_NODES as u64)
+            .map(|k| (Pubkey::new_unique(), k * 1_000_000_000))
+            .collect();
+        let mut simulation = Simulation::builder()
+            .cluster(ClusterSource::Stakes(stakes))
+            .config(config)
+            .seed(42)
+            .build()
+            .unwrap();
+        simulation.step().unwrap();
+        let stakes = simulation.stakes().to_vec();
+        assert!(matches!(
+            simulation.set_epoch_stakes(vec![0; NUM_NODES - 1]),
+            Err(Error::InvalidSimulation(_))
+        ));
+        let epoch_stakes: Vec<u64> = stakes.iter().rev().copied().collect();
+        simulation.set_epoch_stakes(epoch_stakes.clone()).unwrap();
+        assert_eq!(simulation.stakes(), epoch_stakes);
+        // Nodes adopt the new stakes within the lag, and until then keep
+        // their view of the old stakes.
+        let mut num_lagging = 0;
+        for _ in 0..MAX_LAG_ROUNDS {
+            for k in 0..NUM_NODES {
+                let node = simulation.node(NodeId::from(k)).unwrap();
+                assert_eq!(node.stake(), epoch_stakes[k]);
+                assert!(node.stakes() == stakes || node.stakes() == epoch_stakes);
+                num_lagging += usize::from(node.stakes() == stakes);
+            }
+            simulation.step().unwrap();
+        }
+        assert!(num_lagging > 0);
+        for k in 0..NUM_NODES {
+            let node = simulation.node(NodeId::from(k)).unwrap();
+            assert_eq!(node.stakes(), epoch_stakes);
+        }
+    }
+}
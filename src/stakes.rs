@@ -0,0 +1,230 @@
+use {
+    crate::{Error, NodeId},
+    rand::Rng,
+    solana_sdk::{native_token::LAMPORTS_PER_SOL, pubkey::Pubkey},
+    std::{
+        fs::File,
+        io::{BufRead, BufReader, BufWriter, Write},
+        path::Path,
+        str::FromStr,
+        sync::Arc,
+    },
+};
+
+/// Distribution of stakes in a synthetic cluster.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum StakeDistribution {
+    /// All nodes have the same stake.
+    Equal,
+    /// Stakes are uniformly distributed in [0, 2 * mean stake).
+    Uniform,
+    /// Stakes follow a Pareto distribution with the given shape parameter,
+    /// mimicking the heavy tail of mainnet stakes.
+    Pareto(f64),
+}
+
+/// How each node's view of stakes deviates from the stakes of the cluster.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct StakeViewModel {
+    /// Fraction of other nodes whose stake each node knows. Unknown stakes
+    /// are seen as zero.
+    pub known_fraction: f64,
+    /// Known stakes are scaled by a random factor uniformly distributed in
+    /// [1 - noise, 1 + noise].
+    pub noise: f64,
+    /// Nodes adopt stakes of a new epoch after a random number of gossip
+    /// rounds, up to this many. Epochs change only through
+    /// Simulation::set_epoch_stakes, so this is not exposed on the command
+    /// line.
+    pub max_lag_rounds: usize,
+}
+
+impl StakeDistribution {
+    const MEAN_STAKE: u64 = 100_000 * LAMPORTS_PER_SOL;
+    // Scale (i.e. min stake) of the Pareto distribution.
+    const PARETO_MIN_STAKE: u64 = 1_000 * LAMPORTS_PER_SOL;
+    const PARETO_MAX_STAKE: u64 = 20_000_000 * LAMPORTS_PER_SOL;
+
+    fn sample<R: Rng>(&self, rng: &mut R) -> u64 {
+        match self {
+            Self::Equal => Self::MEAN_STAKE,
+            Self::Uniform => rng.gen_range(0, 2 * Self::MEAN_STAKE),
+            Self::Pareto(shape) => {
+                let u: f64 = rng.gen_range(f64::EPSILON, 1.0);
+                let stake = Self::PARETO_MIN_STAKE as f64 * u.powf(-1.0 / shape);
+                (stake as u64).min(Self::PARETO_MAX_STAKE)
+            }
+        }
+    }
+}
+
+impl FromStr for StakeDistribution {
+    type Err = Error;
+
+    // Parses "equal", "uniform" or "pareto:<shape>".
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once(':') {
+            None if s == "equal" => Ok(Self::Equal),
+            None if s == "uniform" => Ok(Self::Uniform),
+            Some(("pareto", shape)) => match shape.parse() {
+                Ok(shape) if shape > 0.0 => Ok(Self::Pareto(shape)),
+                _ => Err(Error::InvalidStakes(format!(
+                    "invalid pareto shape: {shape}"
+                ))),
+            },
+            _ => Err(Error::InvalidStakes(format!("invalid distribution: {s}"))),
+        }
+    }
+}
+
+impl StakeViewModel {
+    /// All nodes see the stakes of the cluster as they are.
+    pub const EXACT: Self = Self {
+        known_fraction: 1.0,
+        noise: 0.0,
+        max_lag_rounds: 0,
+    };
+
+    /// Returns the node's view of the given stakes, indexed by NodeId. The
+    /// node always knows its own stake, and exact views share the stakes.
+    pub fn make_view<R: Rng>(&self, rng: &mut R, node: NodeId, stakes: &Arc<[u64]>) -> Arc<[u64]> {
+        if self.known_fraction >= 1.0 && self.noise == 0.0 {
+            return stakes.clone();
+        }
+        stakes
+            .iter()
+            .enumerate()
+            .map(|(k, &stake)| {
+                if k == node.index() {
+                    stake
+                } else if !rng.gen_bool(self.known_fraction) {
+                    0
+                } else if self.noise == 0.0 {
+                    stake
+                } else {
+                    let scale = rng.gen_range(1.0 - self.noise, 1.0 + self.noise);
+                    (stake as f64 * scale) as u64
+                }
+            })
+            .collect()
+    }
+}
+
+/// Returns node pubkeys and stakes of a synthetic cluster.
+pub fn make_synthetic_stakes<R: Rng>(
+    rng: &mut R,
+    num_nodes: usize,
+    distribution: StakeDistribution,
+) -> Vec<(Pubkey, /*stake:*/ u64)> {
+    (0..num_nodes)
+        .map(|_| {
+            let pubkey = Pubkey::new_from_array(rng.gen());
+            (pubkey, distribution.sample(rng))
+        })
+        .collect()
+}
+
+/// Reads stakes snapshot, one "<node pubkey> <stake>" per line.
+pub fn read_stakes<P: AsRef<Path>>(path: P) -> Result<Vec<(Pubkey, /*stake:*/ u64)>, Error> {
+    let file = BufReader::new(File::open(path)?);
+    let mut stakes = Vec::new();
+    for line in file.lines() {
+        let line = line?;
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let (pubkey, stake) = line
+            .split_once(char::is_whitespace)
+            .ok_or_else(|| Error::InvalidStakes(line.to_string()))?;
+        let pubkey = Pubkey::from_str(pubkey)?;
+        let stake = stake
+            .trim()
+            .parse()
+            .map_err(|_| Error::InvalidStakes(line.to_string()))?;
+        stakes.push((pubkey, stake));
+    }
+    Ok(stakes)
+}
+
+/// Writes stakes snapshot in the format expected by read_stakes.
+pub fn write_stakes<P: AsRef<Path>>(
+    path: P,
+    stakes: &[(Pubkey, /*stake:*/ u64)],
+) -> Result<(), Error> {
+    let mut file = BufWriter::new(File::create(path)?);
+    for (pubkey, stake) in stakes {
+        writeln!(file, "{pubkey} {stake}")?;
+    }
+    file.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use {super::*, rand::SeedableRng, rand_chacha::ChaChaRng};
+
+    #[test]
+    fn test_stake_distribution_from_str() {
+        assert_eq!(
+            "equal".parse::<StakeDistribution>().unwrap(),
+            StakeDistribution::Equal
+        );
+        assert_eq!(
+            "uniform".parse::<StakeDistribution>().unwrap(),
+            StakeDistribution::Uniform
+        );
+        assert_eq!(
+            "pareto:1.5".parse::<StakeDistribution>().unwrap(),
+            StakeDistribution::Pareto(1.5)
+        );
+        assert!("pareto:-1".parse::<StakeDistribution>().is_err());
+        assert!("pareto".parse::<StakeDistribution>().is_err());
+        assert!("zipf:2".parse::<StakeDistribution>().is_err());
+    }
+
+    #[test]
+    fn test_stake_view() {
+        let mut rng = ChaChaRng::from_seed([71u8; 32]);
+        let stakes: Arc<[u64]> = (1..=1000).map(|k| k * LAMPORTS_PER_SOL).collect();
+        let node = NodeId(7);
+        let view = StakeViewModel::EXACT.make_view(&mut rng, node, &stakes);
+        assert!(Arc::ptr_eq(&view, &stakes));
+        // Only the node's own stake is known.
+        let model = StakeViewModel {
+            known_fraction: 0.0,
+            ..StakeViewModel::EXACT
+        };
+        let view = model.make_view(&mut rng, node, &stakes);
+        assert_eq!(view.iter().sum::<u64>(), stakes[node.index()]);
+        assert_eq!(view[node.index()], stakes[node.index()]);
+        let model = StakeViewModel {
+            known_fraction: 0.5,
+            noise: 0.2,
+            max_lag_rounds: 0,
+        };
+        let view = model.make_view(&mut rng, node, &stakes);
+        assert_eq!(view.len(), stakes.len());
+        assert_eq!(view[node.index()], stakes[node.index()]);
+        let num_known = view.iter().filter(|&&stake| stake != 0).count();
+        assert!((400..600).contains(&num_known), "{num_known}");
+        for (&view, &stake) in view.iter().zip(stakes.iter()) {
+            let stake = stake as f64;
+            assert!(view == 0 || (0.8 * stake..=1.2 * stake).contains(&(view as f64)));
+        }
+    }
+
+    #[test]
+    fn test_read_write_stakes() {
+        let mut rng = ChaChaRng::from_seed([71u8; 32]);
+        let stakes = make_synthetic_stakes(&mut rng, 100, StakeDistribution::Pareto(1.2));
+        assert!(stakes.iter().all(|(_, stake)| {
+            (StakeDistribution::PARETO_MIN_STAKE..=StakeDistribution::PARETO_MAX_STAKE)
+                .contains(stake)
+        }));
+        let path = std::env::temp_dir().join(format!("stakes-{}.txt", std::process::id()));
+        write_stakes(&path, &stakes).unwrap();
+        assert_eq!(read_stakes(&path).unwrap(), stakes);
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,260 @@
+use std::collections::HashMap;
+
+/// Returns the probability that each item appears in the first `size`
+/// positions of a weighted shuffle, i.e. when `size` items are sampled
+/// without replacement with probability proportional to their weights.
+///
+/// A weighted shuffle orders items by independent exponential arrival
+/// times T_j with rates w_j. So item i is in the first `size` positions iff
+/// fewer than `size` other items arrive before T_i, and:
+///     P(i) = ∫ w_i e^{-w_i t} P(#{j != i: T_j < t} < size) dt
+/// The integral is evaluated numerically, where items with equal weights
+/// are grouped together, making the cost independent of the number of
+/// items once the number of distinct weights is small (e.g. stake buckets).
+/// Zero weights are shuffled uniformly after all non-zero weights.
+pub fn get_inclusion_probabilities(weights: &[u64], size: usize) -> Vec<f64> {
+    if size == 0 {
+        return vec![0.0; weights.len()];
+    }
+    let num_nonzero = weights.iter().filter(|&&weight| weight != 0).count();
+    if size >= num_nonzero {
+        let num_zeros = weights.len() - num_nonzero;
+        let zero_prob = if num_zeros == 0 {
+            0.0
+        } else {
+            ((size - num_nonzero) as f64 / num_zeros as f64).min(1.0)
+        };
+        return weights
+            .iter()
+            .map(|&weight| if weight == 0 { zero_prob } else { 1.0 })
+            .collect();
+    }
+    let mut groups = HashMap::<u64, usize>::new();
+    for &weight in weights.iter().filter(|&&weight| weight != 0) {
+        *groups.entry(weight).or_default() += 1;
+    }
+    let groups: Vec<(/*weight:*/ u64, /*count:*/ usize)> = groups.into_iter().collect();
+    let probs: HashMap<u64, f64> = groups
+        .iter()
+        .map(|&(weight, _)| {
+            (
+                weight,
+                get_group_inclusion_probability(&groups, weight, size),
+            )
+        })
+        .collect();
+    weights
+        .iter()
+        .map(|weight| probs.get(weight).copied().unwrap_or_default())
+        .collect()
+}
+
+// Returns inclusion probability of an item with the given weight, where
+// groups include the item itself.
+fn get_group_inclusion_probability(groups: &[(u64, usize)], weight: u64, size: usize) -> f64 {
+    const NUM_INTERVALS: usize = 16;
+    const TOLERANCE: f64 = 1e-12;
+    let others: Vec<(f64, usize)> = groups
+        .iter()
+        .map(|&(other, count)| {
+            let count = if other == weight { count - 1 } else { count };
+            (other as f64, count)
+        })
+        .filter(|&(_, count)| count != 0)
+        .collect();
+    // Substituting u = 1 - e^{-w_i t} maps the integral onto [0, 1].
+    let weight = weight as f64;
+    let integrand = |u: f64| {
+        let t = -(-u).ln_1p() / weight;
+        get_arrivals_cdf(&others, t, size)
+    };
+    (0..NUM_INTERVALS)
+        .map(|k| {
+            let a = k as f64 / NUM_INTERVALS as f64;
+            let b = (k + 1) as f64 / NUM_INTERVALS as f64;
+            integrate(&integrand, a, b, TOLERANCE / NUM_INTERVALS as f64)
+        })
+        .sum::<f64>()
+        .clamp(0.0, 1.0)
+}
+
+// Returns the probability that fewer than `size` items have arrived by
+// time t, where each group of items has exponential arrival times with
+// the given rate.
+fn get_arrivals_cdf(groups: &[(/*rate:*/ f64, /*count:*/ usize)], t: f64, size: usize) -> f64 {
+    if t.is_infinite() {
+        let count: usize = groups.iter().map(|(_, count)| count).sum();
+        return if count < size { 1.0 } else { 0.0 };
+    }
+    // dist[k] is the probability that k items have arrived, truncated to
+    // k < size.
+    let mut dist = vec![0.0; size];
+    dist[0] = 1.0;
+    let mut pmf = vec![0.0; size];
+    let mut next = vec![0.0; size];
+    for &(rate, count) in groups {
+        // Number of arrivals in the group is binomial with probability
+        // p = 1 - e^{-rate * t}, computed in log space to avoid underflow.
+        let ln_q = -rate * t;
+        let ln_p = (-ln_q.exp()).ln_1p();
+        let mut ln_binomial = 0.0; // ln(count choose k)
+        for (k, pmf) in pmf.iter_mut().enumerate() {
+            *pmf = if k > count {
+                0.0
+            } else {
+                if k > 0 {
+                    ln_binomial += ((count - k + 1) as f64 / k as f64).ln();
+                }
+                // Skip zero powers, since ln_p is -inf at t = 0.
+                let mut ln_pmf = ln_binomial;
+                if k > 0 {
+                    ln_pmf += k as f64 * ln_p;
+                }
+                if count > k {
+                    ln_pmf += (count - k) as f64 * ln_q;
+                }
+                ln_pmf.exp()
+            };
+        }
+        for (s, next) in next.iter_mut().enumerate() {
+            *next = (0..=s).map(|k| dist[s - k] * pmf[k]).sum();
+        }
+        std::mem::swap(&mut dist, &mut next);
+    }
+    dist.iter().sum()
+}
+
+// Adaptive Simpson's quadrature.
+fn integrate<F: Fn(f64) -> f64>(f: &F, a: f64, b: f64, tolerance: f64) -> f64 {
+    const MAX_DEPTH: usize = 48;
+    let (fa, fm, fb) = (f(a), f((a + b) / 2.0), f(b));
+    let whole = (b - a) / 6.0 * (fa + 4.0 * fm + fb);
+    integrate_recursive(f, a, b, fa, fm, fb, whole, tolerance, MAX_DEPTH)
+}
+
+#[allow(clippy::too_many_arguments)]
+fn integrate_recursive<F: Fn(f64) -> f64>(
+    f: &F,
+    a: f64,
+    b: f64,
+    fa: f64,
+    fm: f64,
+    fb: f64,
+    whole: f64,
+    tolerance: f64,
+    depth: usize,
+) -> f64 {
+    let m = (a + b) / 2.0;
+    let (flm, frm) = (f((a + m) / 2.0), f((m + b) / 2.0));
+    let left = (m - a) / 6.0 * (fa + 4.0 * flm + fm);
+    let right = (b - m) / 6.0 * (fm + 4.0 * frm + fb);
+    let delta = left + right - whole;
+    if depth == 0 || delta.abs() <= 15.0 * tolerance {
+        left + right + delta / 15.0
+    } else {
+        integrate_recursive(f, a, m, fa, flm, fm, left, tolerance / 2.0, depth - 1)
+            + integrate_recursive(f, m, b, fm, frm, fb, right, tolerance / 2.0, depth - 1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        rand::{Rng, SeedableRng},
+        rand_chacha::ChaChaRng,
+        solana_gossip::weighted_shuffle::WeightedShuffle,
+    };
+
+    // Enumerates all orderings of the first `size` positions.
+    fn get_inclusion_probabilities_brute_force(weights: &[u64], size: usize) -> Vec<f64> {
+        fn recurse(
+            weights: &[u64],
+            size: usize,
+            taken: &mut Vec<usize>,
+            prob: f64,
+            out: &mut [f64],
+        ) {
+            if taken.len() == size {
+                for &k in taken.iter() {
+                    out[k] += prob;
+                }
+                return;
+            }
+            let total: u64 = (0..weights.len())
+                .filter(|k| !taken.contains(k))
+                .map(|k| weights[k])
+                .sum();
+            for k in 0..weights.len() {
+                if !taken.contains(&k) {
+                    taken.push(k);
+                    let prob = prob * weights[k] as f64 / total as f64;
+                    recurse(weights, size, taken, prob, out);
+                    taken.pop();
+                }
+            }
+        }
+        let mut out = vec![0.0; weights.len()];
+        recurse(weights, size, &mut Vec::new(), 1.0, &mut out);
+        out
+    }
+
+    #[test]
+    fn test_get_inclusion_probabilities() {
+        let mut rng = ChaChaRng::from_seed([29u8; 32]);
+        for size in 1..5 {
+            for _ in 0..10 {
+                let num_items = rng.gen_range(size, 8);
+                let weights: Vec<u64> = (0..num_items).map(|_| rng.gen_range(1, 30)).collect();
+                let probs = get_inclusion_probabilities(&weights, size);
+                let expected = get_inclusion_probabilities_brute_force(&weights, size);
+                for (prob, expected) in probs.iter().zip(&expected) {
+                    assert!((prob - expected).abs() < 1e-8, "{weights:?}, {size}");
+                }
+                assert!((probs.iter().sum::<f64>() - size as f64).abs() < 1e-8);
+            }
+        }
+    }
+
+    #[test]
+    fn test_get_inclusion_probabilities_zero_weights() {
+        let weights = [0, 3, 0, 5, 0, 0];
+        assert_eq!(
+            get_inclusion_probabilities(&weights, 2),
+            [0.0, 1.0, 0.0, 1.0, 0.0, 0.0]
+        );
+        assert_eq!(
+            get_inclusion_probabilities(&weights, 4),
+            [0.5, 1.0, 0.5, 1.0, 0.5, 0.5]
+        );
+        let probs = get_inclusion_probabilities(&weights, 1);
+        assert!((probs[1] - 0.375).abs() < 1e-9);
+        assert!((probs[3] - 0.625).abs() < 1e-9);
+        assert_eq!(probs[0], 0.0);
+        assert_eq!(get_inclusion_probabilities(&weights, 0), [0.0; 6]);
+        assert_eq!(get_inclusion_probabilities(&[0, 0], 0), [0.0; 2]);
+    }
+
+    #[test]
+    fn test_get_inclusion_probabilities_weighted_shuffle() {
+        const NUM_ROUNDS: usize = 20_000;
+        const SIZE: usize = 6;
+        let mut rng = ChaChaRng::from_seed([53u8; 32]);
+        let weights: Vec<u64> = (0..200)
+            .map(|_| (rng.gen_range(0, 25) + 1u64).pow(2))
+            .collect();
+        let probs = get_inclusion_probabilities(&weights, SIZE);
+        assert!((probs.iter().sum::<f64>() - SIZE as f64).abs() < 1e-6);
+        let mut hits = vec![0usize; weights.len()];
+        for _ in 0..NUM_ROUNDS {
+            let shuffle = WeightedShuffle::new("test-inclusion", &weights).shuffle(&mut rng);
+            for k in shuffle.take(SIZE) {
+                hits[k] += 1;
+            }
+        }
+        for (prob, hits) in probs.into_iter().zip(hits) {
+            let stddev = (NUM_ROUNDS as f64 * prob * (1.0 - prob)).sqrt();
+            assert!((hits as f64 - NUM_ROUNDS as f64 * prob).abs() < 5.0 * stddev + 1.0);
+        }
+    }
+}
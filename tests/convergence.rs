@@ -0,0 +1,260 @@
+use {
+    cluster_mocks::{
+        freshness::get_node_freshness,
+        gossip::{get_crds_table, Config, CrdsEntry, CrdsKey, Discovery, Node},
+        received_cache::{PruneDecision, ReceivedCacheScorer},
+        simulation::{ClusterSource, NetworkModel, Observer, Simulation},
+        stakes::StakeViewModel,
+        NodeId,
+    },
+    rand::{Rng, SeedableRng},
+    rand_chacha::ChaChaRng,
+    solana_sdk::{native_token::LAMPORTS_PER_SOL, pubkey::Pubkey},
+    std::{
+        collections::{BTreeMap, HashMap},
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        },
+        time::Duration,
+    },
+};
+
+const NUM_NODES: usize = 64;
+
+fn make_config() -> Config {
+    Config {
+        gossip_push_fanout: 6.0,
+        gossip_push_wide_fanout: 6.0,
+        rotate_active_set_rounds: 35,
+        gossip_prune_min_ingress_nodes: 3,
+        gossip_prune_max_origins: 32,
+        gossip_prune_timeout: Duration::from_secs(60),
+        gossip_prune_stake_threshold_pct: 0.15,
+        received_cache_capacity: 2 * NUM_NODES,
+        received_cache_min_num_upserts: 20,
+        received_cache_entry_capacity: 50,
+        received_cache_num_dups_threshold: 2,
+        received_cache_scorer: ReceivedCacheScorer::NumDups,
+        gossip_push_capacity: 5,
+        packet_drop_rate: 0.0,
+        crds_values: "vote:8:600:1".parse().unwrap(),
+        discovery: Discovery::Global,
+        stake_view: StakeViewModel::EXACT,
+        crds_purge_rounds: 10,
+        crds_timeout_staked: Duration::from_secs(60),
+        crds_timeout_unstaked: Duration::from_secs(60),
+        num_threads: 1,
+        run_duration: Duration::ZERO,
+        warm_up_rounds: 0,
+        trace: None,
+    }
+}
+
+// Synthetic cluster with a heavy-tailed stake distribution.
+fn make_stakes(seed: u64) -> Vec<(Pubkey, /*stake:*/ u64)> {
+    let mut rng = ChaChaRng::seed_from_u64(seed);
+    (0..NUM_NODES)
+        .map(|_| {
+            let stake = (rng.gen_range(1.0f64, 20.0).exp() as u64 + 1) * LAMPORTS_PER_SOL;
+            (Pubkey::new_unique(), stake)
+        })
+        .collect()
+}
+
+// Synthetic cluster where all nodes fall in the same or adjacent stake
+// buckets.
+fn make_flat_stakes(seed: u64) -> Vec<(Pubkey, /*stake:*/ u64)> {
+    let mut rng = ChaChaRng::seed_from_u64(seed);
+    (0..NUM_NODES)
+        .map(|_| {
+            let stake = rng.gen_range(1_000, 4_000) * LAMPORTS_PER_SOL;
+            (Pubkey::new_unique(), stake)
+        })
+        .collect()
+}
+
+// Returns the mean fraction of the most recent crds values held by nodes.
+fn get_mean_freshness(nodes: &[Node], stakes: &[u64]) -> f64 {
+    let table = get_crds_table(nodes);
+    nodes
+        .iter()
+        .map(|node| get_node_freshness(node, &table, stakes).freshness)
+        .sum::<f64>()
+        / nodes.len() as f64
+}
+
+// Returns the number of (node, crds value) pairs where the node is behind
+// the given ordinal.
+fn get_num_stale(simulation: &Simulation, table: &HashMap<CrdsKey, /*ordinal:*/ u64>) -> usize {
+    (0..simulation.pubkeys().len())
+        .map(|k| {
+            let node = simulation.node(NodeId::from(k)).unwrap();
+            table
+                .iter()
+                .filter(|(key, ordinal)| {
+                    let entry = node.table().get(key);
+                    entry.map(CrdsEntry::ordinal).unwrap_or_default() < **ordinal
+                })
+                .count()
+        })
+        .sum()
+}
+
+// Runs 100 rounds, then asserts that every node catches up with the most
+// recent values as of then within the given number of rounds.
+fn assert_catches_up(mut simulation: Simulation, max_num_rounds: usize) {
+    for _ in 0..100 {
+        simulation.step().unwrap();
+    }
+    let table = {
+        let nodes: Vec<_> = (0..simulation.pubkeys().len())
+            .map(|k| simulation.node(NodeId::from(k)).unwrap())
+            .collect();
+        get_crds_table(nodes.iter().map(|node| &**node))
+    };
+    assert!(get_num_stale(&simulation, &table) > 0);
+    let mut num_rounds = 0;
+    while get_num_stale(&simulation, &table) != 0 {
+        assert!(num_rounds < max_num_rounds);
+        simulation.step().unwrap();
+        num_rounds += 1;
+    }
+}
+
+#[test]
+fn test_convergence_without_drops() {
+    // Where all nodes fall in the same or adjacent stake buckets, they are
+    // sampled into each other's active sets alike, and catch up in a few
+    // rounds.
+    let simulation = Simulation::builder()
+        .cluster(ClusterSource::Stakes(make_flat_stakes(7)))
+        .config(make_config())
+        .seed(7)
+        .build()
+        .unwrap();
+    assert_catches_up(simulation, 10);
+    // Nodes are sampled into active sets with weights growing with their
+    // stake bucket, so low staked nodes in a heavy-tailed cluster may not be
+    // pushed some values until active sets rotate; a real cluster fills
+    // those gaps with pull requests, which are not simulated.
+    let config = make_config();
+    let simulation = Simulation::builder()
+        .cluster(ClusterSource::Stakes(make_stakes(13)))
+        .config(config)
+        .seed(13)
+        .build()
+        .unwrap();
+    assert_catches_up(simulation, 4 * config.rotate_active_set_rounds);
+}
+
+#[test]
+fn test_seeded_runs_are_reproducible() {
+    let run = || {
+        let config = make_config();
+        let mut simulation = Simulation::builder()
+            .cluster(ClusterSource::Stakes(make_stakes(17)))
+            .config(config)
+            .seed(17)
+            .build()
+            .unwrap();
+        for _ in 0..50 {
+            simulation.step().unwrap();
+        }
+        let nodes = simulation.into_nodes().unwrap();
+        nodes
+            .iter()
+            .map(|node| {
+                let table: BTreeMap<CrdsKey, /*ordinal:*/ u64> = node
+                    .table()
+                    .iter()
+                    .map(|(key, entry)| (*key, entry.ordinal()))
+                    .collect();
+                let push_peers: Vec<Vec<NodeId>> = nodes
+                    .iter()
+                    .map(|origin| node.get_push_peers(origin.id(), &config))
+                    .collect();
+                (table, push_peers, format!("{:?}", node.stats()))
+            })
+            .collect::<Vec<_>>()
+    };
+    assert!(run() == run());
+}
+
+#[test]
+fn test_prunes_retain_min_ingress_nodes() {
+    #[derive(Default)]
+    struct PruneObserver {
+        num_decisions: AtomicUsize,
+        num_pruned: AtomicUsize,
+    }
+
+    impl Observer for PruneObserver {
+        fn on_prune(&self, decision: &PruneDecision) {
+            let num_kept = decision
+                .candidates
+                .iter()
+                .filter(|candidate| !candidate.pruned)
+                .count();
+            assert!(
+                num_kept >= decision.min_ingress_nodes.min(decision.candidates.len()),
+                "{decision}"
+            );
+            self.num_decisions.fetch_add(1, Ordering::Relaxed);
+            self.num_pruned
+                .fetch_add(decision.candidates.len() - num_kept, Ordering::Relaxed);
+        }
+    }
+
+    let observer = Arc::new(PruneObserver::default());
+    let mut simulation = Simulation::builder()
+        .cluster(ClusterSource::Stakes(make_stakes(11)))
+        .config(make_config())
+        .seed(11)
+        .observer(observer.clone())
+        .build()
+        .unwrap();
+    for _ in 0..100 {
+        simulation.step().unwrap();
+    }
+    assert!(observer.num_decisions.load(Ordering::Relaxed) > 0);
+    assert!(observer.num_pruned.load(Ordering::Relaxed) > 0);
+}
+
+#[test]
+fn test_freshness_degrades_smoothly_with_drop_rate() {
+    // Simulations are independent, so run them concurrently.
+    let freshness: Vec<f64> = std::thread::scope(|scope| {
+        let handles: Vec<_> = [0.0, 0.1, 0.2, 0.3, 0.4]
+            .into_iter()
+            .map(|packet_drop_rate| {
+                scope.spawn(move || {
+                    let mut simulation = Simulation::builder()
+                        .cluster(ClusterSource::Stakes(make_stakes(13)))
+                        .config(make_config())
+                        .network(NetworkModel { packet_drop_rate })
+                        .seed(13)
+                        .build()
+                        .unwrap();
+                    for _ in 0..100 {
+                        simulation.step().unwrap();
+                    }
+                    let stakes = simulation.stakes().to_vec();
+                    let nodes = simulation.into_nodes().unwrap();
+                    get_mean_freshness(&nodes, &stakes)
+                })
+            })
+            .collect();
+        handles
+            .into_iter()
+            .map(|handle| handle.join().unwrap())
+            .collect()
+    });
+    assert!(freshness[0] > 0.9, "{freshness:?}");
+    // Freshness decreases with the drop rate, but without any sharp drop.
+    for (a, b) in freshness.iter().zip(&freshness[1..]) {
+        assert!(b - a < 0.01, "{freshness:?}");
+        assert!(a - b < 0.1, "{freshness:?}");
+    }
+    assert!(freshness[freshness.len() - 1] > 0.6, "{freshness:?}");
+}